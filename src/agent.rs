@@ -0,0 +1,488 @@
+//! Players that can be driven through a [`Game`] by a [`Match`].
+
+use std::io;
+use std::io::{BufRead, Write};
+
+use super::ai;
+use super::board::{Move, PlayErr, Player};
+use super::game::{Game, GameState};
+use super::rng::Rng;
+use super::solver;
+use super::transposition::TranspositionTable;
+
+pub trait Agent {
+    /// Picks the move to play in `game`, which is still being played.
+    fn choose_play(&mut self, game: &Game) -> Move;
+
+    /// Picks the column of the move [`Agent::choose_play`] picks.
+    ///
+    /// # Panics
+    ///
+    /// When the move is not a drop, which only happens with rules allowing
+    /// other moves.
+    fn choose_move(&mut self, game: &Game) -> usize {
+        self.choose_play(game).expect_drop()
+    }
+}
+
+/// Plays the move found by [`ai::search`], keeping the transposition table
+/// from one move to the next.
+pub struct MinimaxAgent {
+    pub config: ai::SearchConfig,
+    table: TranspositionTable,
+}
+
+impl MinimaxAgent {
+    /// The table is made from `config` here, later changes to its size or
+    /// replacement policy are ignored.
+    pub fn new(config: ai::SearchConfig) -> Self {
+        let table = TranspositionTable::new(config.tt_size, config.tt_replacement);
+        MinimaxAgent { config, table }
+    }
+}
+
+impl Default for MinimaxAgent {
+    fn default() -> Self {
+        MinimaxAgent::new(ai::SearchConfig::default())
+    }
+}
+
+impl Agent for MinimaxAgent {
+    fn choose_play(&mut self, game: &Game) -> Move {
+        ai::search_with_table(game, &self.config, &mut self.table).best_move
+    }
+}
+
+/// Plays any valid move.
+pub struct RandomAgent {
+    rng: Rng,
+}
+
+impl RandomAgent {
+    pub fn new(seed: u64) -> Self {
+        RandomAgent {
+            rng: Rng::new(seed),
+        }
+    }
+}
+
+impl Agent for RandomAgent {
+    fn choose_play(&mut self, game: &Game) -> Move {
+        let moves = game.get_valid_moves();
+        moves[self.rng.below(moves.len() as u64) as usize]
+    }
+}
+
+/// Pieces on the board before a [`SolverAgent`] starts solving by default.
+/// From there positions usually take seconds to solve in release builds.
+pub const DEFAULT_SOLVE_FROM: usize = 8;
+
+/// Plays perfectly with a [`solver::Solver`]. Boards that can't be solved,
+/// and positions with fewer than `solve_from` pieces, are searched with
+/// `fallback` instead.
+pub struct SolverAgent {
+    solver: solver::Solver,
+    /// Pieces on the board before solving starts. Solving gets much slower
+    /// with every piece less: from an empty board it takes hours.
+    pub solve_from: usize,
+    pub fallback: ai::SearchConfig,
+}
+
+impl SolverAgent {
+    pub fn new(table_size: usize) -> Self {
+        SolverAgent {
+            solver: solver::Solver::new(table_size),
+            solve_from: DEFAULT_SOLVE_FROM,
+            fallback: ai::SearchConfig::default(),
+        }
+    }
+}
+
+impl Default for SolverAgent {
+    fn default() -> Self {
+        SolverAgent::new(solver::DEFAULT_TABLE_SIZE)
+    }
+}
+
+impl Agent for SolverAgent {
+    fn choose_play(&mut self, game: &Game) -> Move {
+        if solver::can_solve(game) && game.get_board().get_moves() >= self.solve_from {
+            if let Some(col) = self.solver.solve(game).best_move {
+                return Move::Drop(col);
+            }
+        }
+        ai::search(game, &self.fallback).best_move
+    }
+}
+
+/// Reads a move as typed by a player: a 1-based column to drop a piece in,
+/// or `p` followed by one to pop a piece from.
+pub fn parse_move(input: &str) -> Option<Move> {
+    let input = input.trim();
+    let (pop, column) = match input.strip_prefix('p').or_else(|| input.strip_prefix('P')) {
+        Some(column) => (true, column),
+        None => (false, input),
+    };
+    match column.parse::<usize>() {
+        Ok(col) if col > 0 && pop => Some(Move::Pop(col - 1)),
+        Ok(col) if col > 0 => Some(Move::Drop(col - 1)),
+        _ => None,
+    }
+}
+
+/// What a human typed: a move, or a request to take moves back.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Command {
+    Play(Move),
+    TakeBack,
+}
+
+/// Asks for a move on `input`, in the format read by [`parse_move`], until
+/// a valid one is given. Prompts are written to `output`.
+pub struct HumanAgent<R, W> {
+    input: R,
+    output: W,
+}
+
+impl<R: BufRead, W: Write> HumanAgent<R, W> {
+    pub fn new(input: R, output: W) -> Self {
+        HumanAgent { input, output }
+    }
+
+    /// Writes `prompt` until a valid move, or `u` to take back, is typed.
+    /// Fails when the input ends or either side can't be used.
+    pub fn read_command(&mut self, game: &Game, prompt: &str) -> io::Result<Command> {
+        let valid = game.get_valid_moves();
+        loop {
+            write!(self.output, "{}", prompt)?;
+            self.output.flush()?;
+            let mut buf = String::new();
+            if self.input.read_line(&mut buf)? == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "input ended before the game did",
+                ));
+            }
+            match buf.trim() {
+                "u" | "U" => return Ok(Command::TakeBack),
+                v => match parse_move(v) {
+                    Some(m) if valid.contains(&m) => return Ok(Command::Play(m)),
+                    _ => writeln!(self.output, "Invalid move")?,
+                },
+            }
+        }
+    }
+}
+
+impl HumanAgent<StdinLines, io::Stdout> {
+    /// Reads from stdin and prompts on stdout.
+    pub fn stdin() -> Self {
+        HumanAgent::new(StdinLines::default(), io::stdout())
+    }
+}
+
+impl<R: BufRead, W: Write> Agent for HumanAgent<R, W> {
+    /// # Panics
+    ///
+    /// When the input ends or either side can't be used.
+    fn choose_play(&mut self, game: &Game) -> Move {
+        loop {
+            match self.read_command(game, "Column to play: ") {
+                Ok(Command::Play(m)) => return m,
+                Ok(Command::TakeBack) => {
+                    writeln!(self.output, "Can't take back here").expect("error writing prompt")
+                }
+                Err(e) => panic!("{}", e),
+            }
+        }
+    }
+}
+
+/// Stdin, locked only while a line is read, so that it can be shared with
+/// other readers between lines.
+#[derive(Default)]
+pub struct StdinLines {
+    line: Vec<u8>,
+    pos: usize,
+}
+
+impl io::Read for StdinLines {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl BufRead for StdinLines {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos == self.line.len() {
+            self.line.clear();
+            self.pos = 0;
+            io::stdin().lock().read_until(b'\n', &mut self.line)?;
+        }
+        Ok(&self.line[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = (self.pos + amt).min(self.line.len());
+    }
+}
+
+/// Plays a fixed list of moves, in order.
+pub struct ScriptedAgent {
+    moves: Vec<Move>,
+    next: usize,
+}
+
+impl ScriptedAgent {
+    /// Drops pieces in `columns`, in order.
+    pub fn new(columns: Vec<usize>) -> Self {
+        ScriptedAgent::with_plays(columns.into_iter().map(Move::Drop).collect())
+    }
+
+    /// Plays `moves`, which may include pops, in order.
+    pub fn with_plays(moves: Vec<Move>) -> Self {
+        ScriptedAgent { moves, next: 0 }
+    }
+}
+
+impl Agent for ScriptedAgent {
+    /// # Panics
+    ///
+    /// When every move of the script was already played.
+    fn choose_play(&mut self, _game: &Game) -> Move {
+        let m = *self
+            .moves
+            .get(self.next)
+            .expect("scripted agent ran out of moves");
+        self.next += 1;
+        m
+    }
+}
+
+/// A game between two agents.
+pub struct Match {
+    game: Game,
+    yellow: Box<dyn Agent>,
+    red: Box<dyn Agent>,
+}
+
+impl Match {
+    pub fn new(game: Game, yellow: Box<dyn Agent>, red: Box<dyn Agent>) -> Self {
+        Match { game, yellow, red }
+    }
+
+    /// Asks the agent whose turn it is for a move and plays it. Returns the
+    /// column of the move played, or `None` if the game was already over.
+    /// See [`Match::step_play`] to tell drops from pops.
+    pub fn step(&mut self) -> Result<Option<usize>, PlayErr> {
+        Ok(self.step_play()?.map(|m| m.get_column()))
+    }
+
+    /// Same as [`Match::step`], returning the move played.
+    pub fn step_play(&mut self) -> Result<Option<Move>, PlayErr> {
+        if self.game.state != GameState::Playing {
+            return Ok(None);
+        }
+        let agent = match self.game.current_player {
+            Player::Yellow => &mut self.yellow,
+            Player::Red => &mut self.red,
+        };
+        let m = agent.choose_play(&self.game);
+        self.game.play_move(m)?;
+        Ok(Some(m))
+    }
+
+    /// Steps until the game is over.
+    pub fn play_out(&mut self) -> Result<GameState, PlayErr> {
+        while self.step_play()?.is_some() {}
+        Ok(self.game.state.clone())
+    }
+
+    pub fn get_game(&self) -> &Game {
+        &self.game
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::game::PopOut;
+    use std::sync::Arc;
+
+    #[test]
+    fn scripted_match() -> Result<(), PlayErr> {
+        let mut m = Match::new(
+            Game::new(Player::Yellow),
+            Box::new(ScriptedAgent::new(vec![0, 1, 2, 3])),
+            Box::new(ScriptedAgent::new(vec![6, 6, 6])),
+        );
+
+        assert_eq!(m.step()?, Some(0));
+        assert_eq!(m.get_game().current_player, Player::Red);
+        assert_eq!(m.play_out()?.get_winner(), Some(Player::Yellow));
+        assert_eq!(m.get_game().to_notation(), "1727374");
+        assert_eq!(m.step()?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_moves_are_reported() {
+        let mut m = Match::new(
+            Game::with_size(1, 2, Player::Yellow),
+            Box::new(ScriptedAgent::new(vec![0])),
+            Box::new(ScriptedAgent::new(vec![0])),
+        );
+
+        assert_eq!(m.step(), Ok(Some(0)));
+        assert_eq!(m.step(), Err(PlayErr::FullColumn));
+    }
+
+    #[test]
+    fn random_agents_finish_the_game() -> Result<(), PlayErr> {
+        for seed in 0..10 {
+            let mut m = Match::new(
+                Game::new(Player::Yellow),
+                Box::new(RandomAgent::new(seed)),
+                Box::new(RandomAgent::new(seed + 100)),
+            );
+            assert_ne!(m.play_out()?, GameState::Playing);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn minimax_beats_random() -> Result<(), PlayErr> {
+        let config = ai::SearchConfig {
+            depth: 2,
+            ..ai::SearchConfig::default()
+        };
+        for seed in 0..3 {
+            let mut m = Match::new(
+                Game::new(Player::Yellow),
+                Box::new(RandomAgent::new(seed)),
+                Box::new(MinimaxAgent::new(config.clone())),
+            );
+            assert_eq!(m.play_out()?.get_winner(), Some(Player::Red));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn solver_agent_takes_immediate_win() -> Result<(), PlayErr> {
+        let game = Game::from_moves(6, 7, Player::Yellow, &[0, 6, 1, 6, 2, 5])?;
+        assert_eq!(SolverAgent::default().choose_move(&game), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn solver_agent_searches_openings() {
+        let game = Game::new(Player::Yellow);
+        let mut agent = SolverAgent::default();
+        agent.fallback.depth = 2;
+        assert!(game.get_valid_moves().contains(&agent.choose_play(&game)));
+    }
+
+    #[test]
+    fn human_agent_skips_invalid_input() -> Result<(), PlayErr> {
+        let game = Game::from_moves(1, 3, Player::Yellow, &[1])?;
+        let input: &[u8] = b"x\n0\n2\n4\n3\n";
+        let mut output = Vec::new();
+        assert_eq!(HumanAgent::new(input, &mut output).choose_move(&game), 2);
+        assert_eq!(
+            String::from_utf8_lossy(&output),
+            "Column to play: Invalid move\n".repeat(4) + "Column to play: "
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn human_agent_reads_take_backs() -> Result<(), PlayErr> {
+        let game = Game::from_moves(6, 7, Player::Yellow, &[0])?;
+        let input: &[u8] = b"u\n3\n";
+        let mut human = HumanAgent::new(input, io::sink());
+        assert_eq!(
+            human.read_command(&game, "> ").ok(),
+            Some(Command::TakeBack)
+        );
+        let play = human.read_command(&game, "> ").ok();
+        assert_eq!(play, Some(Command::Play(Move::Drop(2))));
+        let err = human.read_command(&game, "> ").map_err(|e| e.kind());
+        assert_eq!(err, Err(io::ErrorKind::UnexpectedEof));
+
+        let input: &[u8] = b"u\n3\n";
+        assert_eq!(HumanAgent::new(input, io::sink()).choose_move(&game), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn stdin_agents_can_share_stdin() {
+        let _first = HumanAgent::stdin();
+        let _second = HumanAgent::stdin();
+    }
+
+    #[test]
+    fn parses_drops_and_pops() {
+        assert_eq!(parse_move("4\n"), Some(Move::Drop(3)));
+        assert_eq!(parse_move("p1"), Some(Move::Pop(0)));
+        assert_eq!(parse_move("P7"), Some(Move::Pop(6)));
+        assert_eq!(parse_move("p0"), None);
+        assert_eq!(parse_move("p"), None);
+        assert_eq!(parse_move("x"), None);
+    }
+
+    #[test]
+    fn human_agent_pops_only_in_pop_out() -> Result<(), PlayErr> {
+        let mut game = Game::with_rules(6, 7, 4, Arc::new(PopOut), Player::Yellow);
+        game.play(0)?;
+        game.play(1)?;
+        let input: &[u8] = b"p2\np1\n";
+        assert_eq!(
+            HumanAgent::new(input, io::sink()).choose_play(&game),
+            Move::Pop(0)
+        );
+
+        let classic = Game::from_moves(6, 7, Player::Yellow, &[0, 1])?;
+        let input: &[u8] = b"p1\n5\n";
+        assert_eq!(HumanAgent::new(input, io::sink()).choose_move(&classic), 4);
+        Ok(())
+    }
+
+    #[test]
+    fn scripted_agents_can_pop() -> Result<(), PlayErr> {
+        let mut m = Match::new(
+            Game::with_rules(6, 7, 4, Arc::new(PopOut), Player::Yellow),
+            Box::new(ScriptedAgent::with_plays(vec![Move::Drop(0), Move::Pop(0)])),
+            Box::new(ScriptedAgent::new(vec![1])),
+        );
+        assert_eq!(m.step()?, Some(0));
+        assert_eq!(m.step_play()?, Some(Move::Drop(1)));
+        assert_eq!(m.step_play()?, Some(Move::Pop(0)));
+        assert_eq!(m.get_game().to_notation(), "12-1");
+        Ok(())
+    }
+
+    #[test]
+    fn random_agents_finish_pop_out_games() -> Result<(), PlayErr> {
+        for seed in 0..5 {
+            let mut m = Match::new(
+                Game::with_rules(4, 5, 4, Arc::new(PopOut), Player::Yellow),
+                Box::new(RandomAgent::new(seed)),
+                Box::new(RandomAgent::new(seed + 100)),
+            );
+            assert_ne!(m.play_out()?, GameState::Playing);
+        }
+        Ok(())
+    }
+
+    #[test]
+    #[should_panic]
+    fn human_agent_panics_at_end_of_input() {
+        let game = Game::new(Player::Yellow);
+        let input: &[u8] = b"x\n";
+        HumanAgent::new(input, io::sink()).choose_move(&game);
+    }
+}
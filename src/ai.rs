@@ -1,116 +1,735 @@
 use super::board;
+use super::board::Move;
 use super::board_iterators;
 use super::game;
+use super::rng::Rng;
+use super::threats::ThreatWeights;
+use super::transposition::{self, Bound, Entry, Replacement, TranspositionTable};
 use std::cmp;
+use std::time::{Duration, Instant};
 
+/// Scores positions that are not finished for [`search_with`].
+///
+/// Scores are from the point of view of `player`, higher is better, and
+/// should stay well inside `-win / 2..win / 2` of the search weights so
+/// they are not mistaken for a forced result.
+pub trait Evaluator {
+    fn evaluate(&self, game: &game::Game, player: board::Player) -> i32;
+}
+
+/// Points given to a window of four cells, indexed by how many pieces of the
+/// player it holds. A window filled by a single player is worth `win`, or
+/// `-win` for the opponent, which is also the alpha-beta window. Games with
+/// other line lengths score windows of that length, with the count of
+/// pieces scaled to the four entries.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct EvalWeights {
+    pub window: [i32; 4],
+    pub win: i32,
+}
+
+/// Counts the pieces in every window of four cells.
+impl Evaluator for EvalWeights {
+    fn evaluate(&self, game: &game::Game, player: board::Player) -> i32 {
+        calc_score(game, player, self)
+    }
+}
+
+impl Default for EvalWeights {
+    fn default() -> Self {
+        EvalWeights {
+            window: [0, 1, 2, 3],
+            win: 10000,
+        }
+    }
+}
+
+/// Evaluator used by [`search`].
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Evaluation {
+    /// Counts the pieces in every window of four cells, with the points in
+    /// [`SearchConfig::weights`].
+    Windows,
+    /// Looks for threats and their parity, see [`threats`].
+    Threats(ThreatWeights),
+}
+
+/// Heuristics used to search the most promising moves first, which lets
+/// alpha-beta cut more of the tree. Everything is on by default.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct MoveOrdering {
+    /// Columns closer to the center first.
+    pub center_first: bool,
+    /// Best move found for the position by a previous search first, from
+    /// the transposition table or the previous iterative deepening step.
+    pub tt_move: bool,
+    /// Moves that caused a cutoff at the same ply in a sibling position.
+    pub killers: bool,
+    /// Moves that caused cutoffs anywhere in the tree, weighted by depth.
+    pub history: bool,
+}
+
+impl MoveOrdering {
+    /// Searches moves left to right.
+    pub fn none() -> Self {
+        MoveOrdering {
+            center_first: false,
+            tt_move: false,
+            killers: false,
+            history: false,
+        }
+    }
+}
+
+impl Default for MoveOrdering {
+    fn default() -> Self {
+        MoveOrdering {
+            center_first: true,
+            tt_move: true,
+            killers: true,
+            history: true,
+        }
+    }
+}
+
+/// Parameters for [`search`]. Without a time or node limit the search goes
+/// straight to `depth`, otherwise it deepens one level at a time, up to
+/// `depth`, until a limit is hit. Depth one always completes so there is
+/// always a move to return.
+#[derive(Debug, PartialEq, Clone)]
+pub struct SearchConfig {
+    pub depth: usize,
+    pub time_limit: Option<Duration>,
+    pub node_limit: Option<u64>,
+    pub weights: EvalWeights,
+    pub evaluation: Evaluation,
+    /// Size and replacement policy of the transposition table made for
+    /// each search, unused by [`search_with_table`].
+    pub tt_size: usize,
+    pub tt_replacement: Replacement,
+    pub move_ordering: MoveOrdering,
+    /// Largest noise added to the score of each move at the root, so moves
+    /// of similar value are picked at random. Zero keeps the search
+    /// deterministic.
+    pub randomness: i32,
+    pub seed: u64,
+}
+
+impl Default for SearchConfig {
+    fn default() -> Self {
+        SearchConfig {
+            depth: 6,
+            time_limit: None,
+            node_limit: None,
+            weights: EvalWeights::default(),
+            evaluation: Evaluation::Windows,
+            tt_size: transposition::DEFAULT_SIZE,
+            tt_replacement: Replacement::DepthPreferred,
+            move_ordering: MoveOrdering::default(),
+            randomness: 0,
+            seed: 0,
+        }
+    }
+}
+
+/// A game ending that the search proved can be forced, with the number of
+/// moves, counting both players, until it happens.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Forced {
+    Win(usize),
+    Loss(usize),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct SearchResult {
+    pub best_move: Move,
+    pub score: i32,
+    /// Moves both players are expected to make, starting with `best_move`.
+    pub pv: Vec<Move>,
+    pub nodes: u64,
+    /// Nodes where a move was good enough to skip the remaining ones.
+    pub cutoffs: u64,
+    /// Cutoffs caused by the first move searched, the closer to `cutoffs`
+    /// the better the move ordering.
+    pub first_move_cutoffs: u64,
+    pub depth: usize,
+    pub elapsed: Duration,
+    pub forced: Option<Forced>,
+}
+
+impl SearchResult {
+    /// Number of moves of the searching player needed to win, when a win
+    /// can be forced.
+    pub fn win_in(&self) -> Option<usize> {
+        match self.forced {
+            Some(Forced::Win(plies)) => Some(plies.div_ceil(2)),
+            _ => None,
+        }
+    }
+
+    /// Number of moves of the opponent needed to win, when the opponent can
+    /// force a win.
+    pub fn loss_in(&self) -> Option<usize> {
+        match self.forced {
+            Some(Forced::Loss(plies)) => Some(plies / 2),
+            _ => None,
+        }
+    }
+}
+
+/// Limit for [`get_ai_move_with_budget`]. The search always completes depth
+/// one, so a move is found even when the budget is tiny.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Budget {
+    Time(Duration),
+    Nodes(u64),
+}
+
+/// Column to drop a piece in, for games where every move is a drop. Use
+/// [`get_ai_play`] for games with other moves.
+///
+/// # Panics
+///
+/// When the best move is not a drop.
 pub fn get_ai_move(game: &game::Game) -> usize {
-    let max_player = game.current_player;
-    let (ai_move, _) = ab_max(&game, max_player, 6, -10000, 10000);
-    ai_move
+    get_ai_play(game).expect_drop()
 }
 
-fn end_score(game: &game::Game, max_player: board::Player, depth: usize) -> Option<(usize, i32)> {
-    if depth == 0 || game.state != game::GameState::Playing {
-        let last_move = game
-            .get_board()
-            .get_last_move()
-            .expect("should not have a winner if no move were made");
-        return Some((last_move, calc_score(game, max_player)));
+/// Best move found by [`search`] with the default configuration.
+pub fn get_ai_play(game: &game::Game) -> Move {
+    search(game, &SearchConfig::default()).best_move
+}
+
+/// Same as [`get_ai_move`], but searching with the given transposition
+/// table, so its size and replacement policy can be chosen and its entries
+/// reused between calls.
+pub fn get_ai_move_with_table(game: &game::Game, table: &mut TranspositionTable) -> usize {
+    search_with_table(game, &SearchConfig::default(), table)
+        .best_move
+        .expect_drop()
+}
+
+/// Searches one depth at a time until the budget runs out, returning the
+/// best move of the deepest search that finished.
+pub fn get_ai_move_with_budget(game: &game::Game, budget: Budget) -> usize {
+    let mut config = SearchConfig {
+        depth: usize::MAX,
+        ..SearchConfig::default()
+    };
+    match budget {
+        Budget::Time(t) => config.time_limit = Some(t),
+        Budget::Nodes(n) => config.node_limit = Some(n),
+    }
+    search(game, &config).best_move.expect_drop()
+}
+
+pub fn search(game: &game::Game, config: &SearchConfig) -> SearchResult {
+    let table = &mut TranspositionTable::new(config.tt_size, config.tt_replacement);
+    search_with_table(game, config, table)
+}
+
+/// Same as [`search`], but with the given transposition table instead of a
+/// new one made from `config`, so its entries are reused between calls.
+pub fn search_with_table(
+    game: &game::Game,
+    config: &SearchConfig,
+    table: &mut TranspositionTable,
+) -> SearchResult {
+    match &config.evaluation {
+        Evaluation::Windows => run_search(game, config, &config.weights, table),
+        Evaluation::Threats(weights) => run_search(game, config, weights, table),
     }
+}
 
-    None
+/// Same as [`search`], scoring positions with `evaluator` instead of the
+/// one chosen by `config.evaluation`.
+pub fn search_with(
+    game: &game::Game,
+    config: &SearchConfig,
+    evaluator: &dyn Evaluator,
+) -> SearchResult {
+    let table = &mut TranspositionTable::new(config.tt_size, config.tt_replacement);
+    run_search(game, config, evaluator, table)
 }
 
-fn ab_min(game: &game::Game, max_player: board::Player, depth: usize, alpha: i32, beta: i32) -> (usize, i32) {
-    if let Some(score) = end_score(game, max_player, depth) {
-        return score;
+fn run_search(
+    game: &game::Game,
+    config: &SearchConfig,
+    evaluator: &dyn Evaluator,
+    table: &mut TranspositionTable,
+) -> SearchResult {
+    let mut searcher = Searcher::new(game, table, config, evaluator);
+    let mut game = game.clone();
+    let depth = cmp::max(config.depth, 1);
+
+    if config.time_limit.is_none() && config.node_limit.is_none() {
+        let (best_move, score) = searcher.search_root(&mut game, depth, None);
+        return searcher.result(&game, best_move, score, depth);
     }
 
-    let mut beta = beta;
-    let mut best_score = 10000;
-    let mut best_move = 0;
-    for m in game.get_board().get_valid_moves() {
-        let mut copy_state = game.clone();
-        copy_state.play(m).expect("should be valid move");
-        let (_, score) = ab_max(&copy_state, max_player, depth - 1, alpha, beta);
-        if score < best_score {
-            best_score = score;
-            best_move = m;
+    let max_depth = match game.get_rules().max_moves_left(game.get_board()) {
+        Some(left) => cmp::max(cmp::min(depth, left), 1),
+        None => depth,
+    };
+    let mut completed = None;
+    for d in 1..=max_depth {
+        let first = completed.map(|(m, _, _)| m);
+        let (best_move, score) = searcher.search_root(&mut game, d, first);
+        if searcher.aborted {
+            break;
         }
-        beta = cmp::min(beta, best_score);
-        if beta <= alpha {
+        completed = Some((best_move, score, d));
+        searcher.can_abort = true;
+        if score.abs() >= config.weights.win / 2 {
             break;
         }
     }
-    (best_move, best_score)
+    let (best_move, score, d) = completed.expect("depth one always completes");
+    searcher.result(&game, best_move, score, d)
 }
 
-fn ab_max(game: &game::Game, max_player: board::Player, depth: usize, alpha: i32, beta: i32) -> (usize, i32) {
-    if let Some(val) = end_score(game, max_player, depth) {
-        return val;
+struct Searcher<'a> {
+    max_player: board::Player,
+    root_ply: usize,
+    table: &'a mut TranspositionTable,
+    config: &'a SearchConfig,
+    evaluator: &'a dyn Evaluator,
+    rng: Rng,
+    start: Instant,
+    nodes: u64,
+    cutoffs: u64,
+    first_move_cutoffs: u64,
+    killers: Vec<[Option<Move>; 2]>,
+    history: [Vec<u64>; 2],
+    can_abort: bool,
+    aborted: bool,
+    next_clock_check: u64,
+}
+
+/// Nodes searched between two looks at the clock.
+const CLOCK_INTERVAL: u64 = 64;
+
+impl<'a> Searcher<'a> {
+    fn new(
+        game: &game::Game,
+        table: &'a mut TranspositionTable,
+        config: &'a SearchConfig,
+        evaluator: &'a dyn Evaluator,
+    ) -> Self {
+        let columns = game.get_board().get_columns();
+        Searcher {
+            max_player: game.current_player,
+            root_ply: game.history().len(),
+            table,
+            config,
+            evaluator,
+            rng: Rng::new(config.seed),
+            start: Instant::now(),
+            nodes: 0,
+            cutoffs: 0,
+            first_move_cutoffs: 0,
+            killers: Vec::new(),
+            history: [vec![0; 2 * columns], vec![0; 2 * columns]],
+            can_abort: false,
+            aborted: false,
+            next_clock_check: 0,
+        }
     }
 
-    let mut alpha = alpha;
-    let mut best_score = -10000;
-    let mut best_move = 0;
-    for m in game.get_board().get_valid_moves() {
-        let mut copy_state = game.clone();
-        copy_state.play(m).expect("should be valid move");
-        let (_, score) = ab_min(&copy_state, max_player, depth - 1, alpha, beta);
-        if score > best_score {
-            best_score = score;
-            best_move = m;
+    fn result(&self, game: &game::Game, best_move: Move, score: i32, depth: usize) -> SearchResult {
+        let pv = self.principal_variation(game, best_move, depth);
+        let win = self.config.weights.win;
+        let plies = (win - score.abs()) as usize;
+        let forced = if score >= win / 2 {
+            Some(Forced::Win(plies))
+        } else if score <= -win / 2 {
+            Some(Forced::Loss(plies))
+        } else {
+            None
+        };
+
+        SearchResult {
+            best_move,
+            score,
+            pv,
+            nodes: self.nodes,
+            cutoffs: self.cutoffs,
+            first_move_cutoffs: self.first_move_cutoffs,
+            depth,
+            elapsed: self.start.elapsed(),
+            forced,
         }
-        alpha = cmp::max(alpha, best_score);
-        if alpha >= beta {
-            break;
+    }
+
+    /// Follows the best moves stored in the transposition table from the
+    /// root, stopping when an entry is missing or the game ends.
+    fn principal_variation(&self, game: &game::Game, best_move: Move, depth: usize) -> Vec<Move> {
+        let mut game = game.clone();
+        let mut pv = Vec::new();
+        let mut next = Some(best_move);
+        while let Some(m) = next {
+            if pv.len() >= depth || game.play_move(m).is_err() {
+                break;
+            }
+            pv.push(m);
+            if game.state != game::GameState::Playing {
+                break;
+            }
+            next = self
+                .table
+                .probe(self.position_key(&game))
+                .map(|e| e.best_move);
+        }
+        pv
+    }
+
+    /// Checks the limits, looking at the clock only once every
+    /// `CLOCK_INTERVAL` nodes, leaves included, as reading it is slow.
+    fn out_of_budget(&mut self) -> bool {
+        if !self.can_abort || self.aborted {
+            return self.aborted;
+        }
+        let out_of_nodes = self.config.node_limit.is_some_and(|n| self.nodes > n);
+        let mut out_of_time = false;
+        if self.nodes >= self.next_clock_check {
+            self.next_clock_check = self.nodes + CLOCK_INTERVAL;
+            out_of_time = self
+                .config
+                .time_limit
+                .is_some_and(|t| self.start.elapsed() >= t);
+        }
+        self.aborted = out_of_nodes || out_of_time;
+        self.aborted
+    }
+
+    /// Scores depend on the player the search is made for, so it is part of
+    /// the key along with the player to move.
+    fn position_key(&self, game: &game::Game) -> u64 {
+        let mut key = game.get_board().get_hash() ^ game.get_rule_state().get_hash();
+        if game.current_player == board::Player::Red {
+            key ^= 0x5A5A_5A5A_5A5A_5A5A;
+        }
+        if self.max_player == board::Player::Red {
+            key ^= 0xA5A5_0000_A5A5_0000;
+        }
+        key
+    }
+
+    /// Scores the position for the player to move. Finished games score
+    /// `win` minus the number of moves it took to get there, so faster wins
+    /// and slower losses are preferred.
+    fn end_score(&self, game: &game::Game, depth: usize) -> Option<(Move, i32)> {
+        if depth == 0 || game.state != game::GameState::Playing {
+            let last_move = game
+                .get_board()
+                .get_last_play()
+                .expect("should not have a winner if no move were made");
+            let ply = self.ply(game) as i32;
+            let win = self.config.weights.win;
+            let score = match &game.state {
+                game::GameState::Winner(info) if info.player == game.current_player => win - ply,
+                game::GameState::Winner(_) => ply - win,
+                game::GameState::Tie => 0,
+                game::GameState::Playing => self.evaluate(game),
+            };
+            return Some((last_move, score));
+        }
+
+        None
+    }
+
+    /// The evaluation is made for the player the search runs for and
+    /// negated on the opponent's turns, so it reads the same as it did for
+    /// a min/max pair.
+    fn evaluate(&self, game: &game::Game) -> i32 {
+        let score = self.evaluator.evaluate(game, self.max_player);
+        if game.current_player == self.max_player {
+            score
+        } else {
+            -score
         }
     }
-    (best_move, best_score)
+
+    /// Searches the root position. With randomness every move is searched
+    /// with a full window, so noise is added to exact scores.
+    fn search_root(
+        &mut self,
+        game: &mut game::Game,
+        depth: usize,
+        first: Option<Move>,
+    ) -> (Move, i32) {
+        let win = self.config.weights.win;
+        if self.config.randomness <= 0 {
+            return self.negamax(game, depth, -win, win, first);
+        }
+        if let Some(val) = self.end_score(game, depth) {
+            return val;
+        }
+
+        let mut best: Option<(Move, i32, i32)> = None;
+        let mover = game.current_player;
+        for m in self.ordered_moves(game, first) {
+            game.play_move(m).expect("should be valid move");
+            let score = self.score_after(game, mover, depth - 1, -win, win);
+            game.undo();
+            if self.aborted {
+                return (Move::Drop(0), 0);
+            }
+            let noisy = score + self.rng.noise(self.config.randomness);
+            if best.is_none_or(|(_, _, b)| noisy > b) {
+                best = Some((m, score, noisy));
+            }
+        }
+        let (best_move, score, _) = best.expect("a game in progress has valid moves");
+        (best_move, score)
+    }
+
+    /// Orders the moves as `first`, then the killers, then by history score,
+    /// with ties broken by the distance to the center.
+    fn ordered_moves(&self, game: &game::Game, first: Option<Move>) -> Vec<Move> {
+        let ordering = &self.config.move_ordering;
+        let mut moves = game.get_valid_moves();
+        let columns = game.get_board().get_columns();
+        if ordering.center_first {
+            let columns = columns as isize;
+            moves.sort_by_key(|&m| (2 * m.get_column() as isize - (columns - 1)).abs());
+        }
+        if ordering.history {
+            let history = &self.history[game.current_player.index()];
+            moves.sort_by_key(|&m| cmp::Reverse(history[history_index(m, columns)]));
+        }
+        if ordering.killers {
+            if let Some(killers) = self.killers.get(self.ply(game)) {
+                for &k in killers.iter().rev().flatten() {
+                    move_to_front(&mut moves, k);
+                }
+            }
+        }
+        if ordering.tt_move {
+            if let Some(f) = first {
+                move_to_front(&mut moves, f);
+            }
+        }
+        moves
+    }
+
+    fn ply(&self, game: &game::Game) -> usize {
+        game.history().len() - self.root_ply
+    }
+
+    fn record_cutoff(&mut self, game: &game::Game, m: Move, depth: usize, index: usize) {
+        self.cutoffs += 1;
+        if index == 0 {
+            self.first_move_cutoffs += 1;
+        }
+
+        let ply = self.ply(game);
+        if self.killers.len() <= ply {
+            self.killers.resize(ply + 1, [None, None]);
+        }
+        let killers = &mut self.killers[ply];
+        if killers[0] != Some(m) {
+            killers[1] = killers[0];
+            killers[0] = Some(m);
+        }
+
+        let index = history_index(m, game.get_board().get_columns());
+        self.history[game.current_player.index()][index] += (depth * depth) as u64;
+    }
+
+    /// Scores the position reached by a move of `mover` for `mover`, which
+    /// is the negated score for the opponent unless the rules let `mover`
+    /// play again.
+    fn score_after(
+        &mut self,
+        game: &mut game::Game,
+        mover: board::Player,
+        depth: usize,
+        alpha: i32,
+        beta: i32,
+    ) -> i32 {
+        if game.current_player == mover {
+            self.negamax(game, depth, alpha, beta, None).1
+        } else {
+            -self.negamax(game, depth, -beta, -alpha, None).1
+        }
+    }
+
+    /// Scores are from the point of view of the player to move. `first` is
+    /// searched before any other move, which lets iterative deepening start
+    /// from the best move of the previous depth.
+    fn negamax(
+        &mut self,
+        game: &mut game::Game,
+        depth: usize,
+        alpha: i32,
+        beta: i32,
+        first: Option<Move>,
+    ) -> (Move, i32) {
+        self.nodes += 1;
+        if self.out_of_budget() {
+            return (Move::Drop(0), 0);
+        }
+        if let Some(val) = self.end_score(game, depth) {
+            return val;
+        }
+
+        let key = self.position_key(game);
+        let ply = self.ply(game) as i32;
+        let win = self.config.weights.win;
+        let entry = self.table.probe(key).map(|e| Entry {
+            score: score_from_table(e.score, ply, win),
+            ..e
+        });
+        if first.is_none() {
+            if let Some(cutoff) = tt_cutoff(entry, depth, alpha, beta) {
+                return cutoff;
+            }
+        }
+
+        let alpha_orig = alpha;
+        let mut alpha = alpha;
+        let mut best_score = -self.config.weights.win;
+        let mut best_move = Move::Drop(0);
+        let mover = game.current_player;
+        let moves = self.ordered_moves(game, first.or_else(|| entry.map(|e| e.best_move)));
+        for (i, m) in moves.into_iter().enumerate() {
+            game.play_move(m).expect("should be valid move");
+            let score = self.score_after(game, mover, depth - 1, alpha, beta);
+            game.undo();
+            if self.aborted {
+                return (Move::Drop(0), 0);
+            }
+            if score > best_score {
+                best_score = score;
+                best_move = m;
+            }
+            alpha = cmp::max(alpha, best_score);
+            if alpha >= beta {
+                self.record_cutoff(game, m, depth, i);
+                break;
+            }
+        }
+
+        let bound = if best_score >= beta {
+            Bound::Lower
+        } else if best_score <= alpha_orig {
+            Bound::Upper
+        } else {
+            Bound::Exact
+        };
+        self.table.store(Entry {
+            key,
+            depth,
+            score: score_to_table(best_score, ply, win),
+            bound,
+            best_move,
+        });
+        (best_move, best_score)
+    }
+}
+
+/// Forced results score `win` minus the plies from the root to the end of
+/// the game. The table keeps the plies from the position itself instead, so
+/// its scores stay right when the position comes up at another ply or the
+/// table is reused for another root.
+fn score_to_table(score: i32, ply: i32, win: i32) -> i32 {
+    if score >= win / 2 {
+        score + ply
+    } else if score <= -win / 2 {
+        score - ply
+    } else {
+        score
+    }
+}
+
+/// Undoes [`score_to_table`] for a position `ply` plies from the root.
+fn score_from_table(score: i32, ply: i32, win: i32) -> i32 {
+    if score >= win / 2 {
+        score - ply
+    } else if score <= -win / 2 {
+        score + ply
+    } else {
+        score
+    }
+}
+
+/// Drops come first in the history table, followed by pops.
+fn history_index(m: Move, columns: usize) -> usize {
+    match m {
+        Move::Drop(col) => col,
+        Move::Pop(col) => columns + col,
+    }
+}
+
+fn move_to_front(moves: &mut [Move], m: Move) {
+    if let Some(pos) = moves.iter().position(|&x| x == m) {
+        moves[..=pos].rotate_right(1);
+    }
 }
 
-fn calc_score(game: &game::Game, player: board::Player) -> i32 {
-    score_columns(game, player)
-        + score_rows(game, player)
-        + score_diagonals(game, player)
+fn tt_cutoff(entry: Option<Entry>, depth: usize, alpha: i32, beta: i32) -> Option<(Move, i32)> {
+    let e = entry.filter(|e| e.depth >= depth)?;
+    match e.bound {
+        Bound::Exact => Some((e.best_move, e.score)),
+        Bound::Lower if e.score >= beta => Some((e.best_move, e.score)),
+        Bound::Upper if e.score <= alpha => Some((e.best_move, e.score)),
+        _ => None,
+    }
 }
 
-fn score_columns(game: &game::Game, player: board::Player) -> i32 {
+fn calc_score(game: &game::Game, player: board::Player, weights: &EvalWeights) -> i32 {
+    score_columns(game, player, weights)
+        + score_rows(game, player, weights)
+        + score_diagonals(game, player, weights)
+}
+
+fn score_columns(game: &game::Game, player: board::Player, weights: &EvalWeights) -> i32 {
     let mut max = 0;
     for col in board_iterators::ColIterator::new(game.get_board()) {
-        max += calculate_points(col, player);
+        max += calculate_points(col, player, weights, game.get_connect());
     }
     max
 }
 
-fn score_rows(game: &game::Game, player: board::Player) -> i32 {
+fn score_rows(game: &game::Game, player: board::Player, weights: &EvalWeights) -> i32 {
     let mut max = 0;
-    for col in board_iterators::RowIterator::new(game.get_board()) {
-        max += calculate_points(col, player);
+    let rows = board_iterators::RowIterator::wrapping(game.get_board(), game.get_connect());
+    for col in rows {
+        max += calculate_points(col, player, weights, game.get_connect());
     }
     max
 }
 
-fn score_diagonals(game: &game::Game, player: board::Player) -> i32 {
+fn score_diagonals(game: &game::Game, player: board::Player, weights: &EvalWeights) -> i32 {
     let mut max = 0;
     let diag_it = board_iterators::MainDiagonalIterator::new(game.get_board()).chain(
         board_iterators::SecondaryDiagonalIterator::new(game.get_board()),
     );
     for col in diag_it {
-        max += calculate_points(col, player);
+        max += calculate_points(col, player, weights, game.get_connect());
     }
     max
 }
 
-fn calculate_points(pieces: Vec<&Option<board::Player>>, player: board::Player) -> i32 {
+fn calculate_points(
+    pieces: Vec<&Option<board::Player>>,
+    player: board::Player,
+    weights: &EvalWeights,
+    connect: usize,
+) -> i32 {
     let mut score = 0;
-    for w in pieces.windows(4) {
-        score += score_window(w, Some(player));
+    for w in pieces.windows(connect) {
+        score += score_window(w, Some(player), weights);
     }
     score
 }
 
-fn score_window(pieces: &[&Option<board::Player>], wanted_piece: Option<board::Player>) -> i32 {
+fn score_window(
+    pieces: &[&Option<board::Player>],
+    wanted_piece: Option<board::Player>,
+    weights: &EvalWeights,
+) -> i32 {
     let mut score = 0;
     let mut op_score = 0;
     let op_player = if let Some(player) = wanted_piece {
@@ -130,13 +749,445 @@ fn score_window(pieces: &[&Option<board::Player>], wanted_piece: Option<board::P
         }
     }
 
-    if op_score == 4 {
-        return -10000;
+    if op_score == pieces.len() {
+        return -weights.win;
     }
 
-    if score == 4 {
-        return 10000;
+    if score == pieces.len() {
+        return weights.win;
     }
 
-    score
+    weights.window[score * 3 / cmp::max(pieces.len() - 1, 1)]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Arc;
+
+    fn with_limits(time_limit: Option<Duration>, node_limit: Option<u64>) -> SearchConfig {
+        SearchConfig {
+            depth: usize::MAX,
+            time_limit,
+            node_limit,
+            ..SearchConfig::default()
+        }
+    }
+
+    #[test]
+    fn takes_immediate_win() -> Result<(), board::PlayErr> {
+        let game = game::Game::from_moves(6, 7, board::Player::Yellow, &[0, 6, 1, 6, 2, 5])?;
+        assert_eq!(get_ai_move(&game), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn blocks_immediate_loss() -> Result<(), board::PlayErr> {
+        let game = game::Game::from_moves(6, 7, board::Player::Yellow, &[3, 0, 3, 0, 3])?;
+        assert_eq!(get_ai_move(&game), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn table_size_does_not_change_the_move() -> Result<(), board::PlayErr> {
+        let game = game::Game::from_moves(6, 7, board::Player::Yellow, &[3, 3, 2, 4])?;
+        let tiny = SearchConfig {
+            tt_size: 1,
+            tt_replacement: Replacement::Always,
+            ..SearchConfig::default()
+        };
+        assert_eq!(
+            search(&game, &tiny).best_move,
+            search(&game, &SearchConfig::default()).best_move
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn reused_table_saves_nodes() -> Result<(), board::PlayErr> {
+        let game = game::Game::from_moves(6, 7, board::Player::Yellow, &[3, 3, 2, 4])?;
+        let config = SearchConfig::default();
+        let mut table = TranspositionTable::default();
+        let first = search_with_table(&game, &config, &mut table);
+        let second = search_with_table(&game, &config, &mut table);
+
+        assert_eq!(second.best_move, first.best_move);
+        assert!(second.nodes < first.nodes);
+        assert_eq!(
+            get_ai_move_with_table(&game, &mut table),
+            get_ai_move(&game)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn reused_table_keeps_forced_wins_exact() -> Result<(), board::PlayErr> {
+        let earlier = game::Game::from_moves(6, 7, board::Player::Yellow, &[2, 6])?;
+        let config = SearchConfig {
+            depth: 8,
+            ..SearchConfig::default()
+        };
+        let mut table = TranspositionTable::default();
+        search_with_table(&earlier, &config, &mut table);
+
+        let game = game::Game::from_moves(6, 7, board::Player::Yellow, &[2, 6, 3, 6])?;
+        let limited = with_limits(Some(Duration::from_millis(300)), None);
+        let result = search_with_table(&game, &limited, &mut table);
+
+        assert_eq!(result.forced, Some(Forced::Win(3)));
+        assert_eq!(result.score, 10000 - 3);
+        Ok(())
+    }
+
+    #[test]
+    fn budget_takes_immediate_win() -> Result<(), board::PlayErr> {
+        let game = game::Game::from_moves(6, 7, board::Player::Yellow, &[0, 6, 1, 6, 2, 5])?;
+        assert_eq!(get_ai_move_with_budget(&game, Budget::Nodes(5000)), 3);
+        let budget = Budget::Time(Duration::from_millis(50));
+        assert_eq!(get_ai_move_with_budget(&game, budget), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn limited_search_takes_immediate_win() -> Result<(), board::PlayErr> {
+        let game = game::Game::from_moves(6, 7, board::Player::Yellow, &[0, 6, 1, 6, 2, 5])?;
+        let by_nodes = with_limits(None, Some(5000));
+        let by_time = with_limits(Some(Duration::from_millis(50)), None);
+        assert_eq!(search(&game, &by_nodes).best_move, Move::Drop(3));
+        assert_eq!(search(&game, &by_time).best_move, Move::Drop(3));
+        Ok(())
+    }
+
+    #[test]
+    fn tiny_limit_still_returns_a_valid_move() -> Result<(), board::PlayErr> {
+        let game = game::Game::from_moves(6, 7, board::Player::Red, &[3, 3, 3, 3, 3, 3])?;
+        let m = search(&game, &with_limits(None, Some(0))).best_move;
+        assert!(game.get_valid_moves().contains(&m));
+        Ok(())
+    }
+
+    #[test]
+    fn time_limit_is_respected() {
+        let game = game::Game::new(board::Player::Yellow);
+        let start = Instant::now();
+        search(&game, &with_limits(Some(Duration::from_millis(100)), None));
+        assert!(start.elapsed() < Duration::from_millis(150));
+    }
+
+    #[test]
+    fn short_time_limit_is_barely_overrun() -> Result<(), board::PlayErr> {
+        let game = game::Game::from_moves(6, 7, board::Player::Yellow, &[3, 3, 2, 4])?;
+        let config = SearchConfig {
+            evaluation: Evaluation::Threats(ThreatWeights::default()),
+            ..with_limits(Some(Duration::from_millis(50)), None)
+        };
+        let start = Instant::now();
+        search(&game, &config);
+        let elapsed = start.elapsed();
+        assert!(elapsed < Duration::from_millis(75), "took {:?}", elapsed);
+        Ok(())
+    }
+
+    #[test]
+    fn randomness_varies_the_move() {
+        let game = game::Game::new(board::Player::Yellow);
+        let moves: Vec<Move> = (0..20)
+            .map(|seed| {
+                let config = SearchConfig {
+                    depth: 2,
+                    randomness: 1000,
+                    seed,
+                    ..SearchConfig::default()
+                };
+                search(&game, &config).best_move
+            })
+            .collect();
+        assert!(moves.iter().any(|&m| m != moves[0]));
+
+        let deterministic = SearchConfig {
+            depth: 2,
+            ..SearchConfig::default()
+        };
+        let first = search(&game, &deterministic).best_move;
+        assert!((0..5).all(|_| search(&game, &deterministic).best_move == first));
+    }
+
+    #[test]
+    fn weights_change_the_score() -> Result<(), board::PlayErr> {
+        let game = game::Game::from_moves(6, 7, board::Player::Yellow, &[3, 3, 2, 4])?;
+        let flat = SearchConfig {
+            depth: 2,
+            weights: EvalWeights {
+                window: [0, 0, 0, 0],
+                win: 10000,
+            },
+            ..SearchConfig::default()
+        };
+        assert_eq!(search(&game, &flat).score, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn result_reports_search_details() -> Result<(), board::PlayErr> {
+        let game = game::Game::from_moves(6, 7, board::Player::Yellow, &[3, 3, 2, 4])?;
+        let result = search(&game, &SearchConfig::default());
+
+        assert_eq!(result.pv.first(), Some(&result.best_move));
+        assert!(!result.pv.is_empty() && result.pv.len() <= 6);
+        assert_eq!(result.depth, 6);
+        assert!(result.nodes > 0);
+        assert_eq!(result.forced, None);
+        Ok(())
+    }
+
+    #[test]
+    fn result_reports_forced_win() -> Result<(), board::PlayErr> {
+        let game = game::Game::from_moves(6, 7, board::Player::Yellow, &[0, 6, 1, 6, 2, 5])?;
+        let result = search(&game, &SearchConfig::default());
+
+        assert_eq!(result.pv, vec![Move::Drop(3)]);
+        assert_eq!(result.forced, Some(Forced::Win(1)));
+        assert_eq!(result.score, 10000 - 1);
+        assert_eq!(result.win_in(), Some(1));
+        Ok(())
+    }
+
+    #[test]
+    fn prefers_faster_wins() -> Result<(), board::PlayErr> {
+        let game = game::Game::from_moves(6, 7, board::Player::Yellow, &[2, 6, 3, 6])?;
+        let result = search(&game, &SearchConfig::default());
+
+        assert!(result.best_move == Move::Drop(1) || result.best_move == Move::Drop(4));
+        assert_eq!(result.forced, Some(Forced::Win(3)));
+        assert_eq!(result.score, 10000 - 3);
+        assert_eq!(result.win_in(), Some(2));
+        Ok(())
+    }
+
+    #[test]
+    fn wins_at_once_instead_of_delaying() -> Result<(), board::PlayErr> {
+        let game = game::Game::from_moves(6, 7, board::Player::Yellow, &[2, 0, 3, 0, 4, 6])?;
+        let result = search(&game, &SearchConfig::default());
+
+        assert!(result.best_move == Move::Drop(1) || result.best_move == Move::Drop(5));
+        assert_eq!(result.win_in(), Some(1));
+        Ok(())
+    }
+
+    #[test]
+    fn iterative_deepening_stops_at_forced_result() -> Result<(), board::PlayErr> {
+        let game = game::Game::from_moves(6, 7, board::Player::Yellow, &[0, 6, 1, 6, 2, 5])?;
+        let result = search(&game, &with_limits(Some(Duration::from_secs(5)), None));
+
+        assert_eq!(result.depth, 1);
+        assert_eq!(result.best_move, Move::Drop(3));
+        Ok(())
+    }
+
+    #[test]
+    fn result_reports_forced_loss() -> Result<(), board::PlayErr> {
+        let game = game::Game::from_moves(6, 7, board::Player::Yellow, &[1, 6, 2, 6, 3])?;
+        let result = search(&game, &SearchConfig::default());
+
+        assert_eq!(result.forced, Some(Forced::Loss(2)));
+        assert_eq!(result.loss_in(), Some(1));
+        assert_eq!(result.pv.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn limited_search_reports_completed_depth() {
+        let game = game::Game::new(board::Player::Yellow);
+        let result = search(&game, &with_limits(None, Some(2000)));
+
+        assert!(result.depth >= 1 && result.depth < 42);
+        assert!(result.nodes > 2000);
+    }
+
+    #[test]
+    fn move_ordering_reduces_nodes() -> Result<(), board::PlayErr> {
+        let game = game::Game::from_moves(6, 7, board::Player::Yellow, &[3, 3, 2, 4])?;
+        let unordered = SearchConfig {
+            move_ordering: MoveOrdering::none(),
+            ..SearchConfig::default()
+        };
+        let killers_only = SearchConfig {
+            move_ordering: MoveOrdering {
+                killers: true,
+                ..MoveOrdering::none()
+            },
+            ..SearchConfig::default()
+        };
+        let plain = search(&game, &unordered);
+        let killers = search(&game, &killers_only);
+        let ordered = search(&game, &SearchConfig::default());
+
+        assert!(killers.nodes < plain.nodes);
+        assert!(ordered.nodes < plain.nodes);
+        assert_eq!(ordered.score, plain.score);
+        Ok(())
+    }
+
+    #[test]
+    fn result_reports_cutoffs() -> Result<(), board::PlayErr> {
+        let game = game::Game::from_moves(6, 7, board::Player::Yellow, &[3, 3, 2, 4])?;
+        let result = search(&game, &SearchConfig::default());
+
+        assert!(result.cutoffs > 0);
+        assert!(result.first_move_cutoffs <= result.cutoffs);
+        Ok(())
+    }
+
+    #[test]
+    fn threat_evaluation_stops_open_three() -> Result<(), board::PlayErr> {
+        // Yellow makes an open three with two ways to win if it gets to
+        // play 1 or 4, which windows counting misses at shallow depths.
+        let game = game::Game::from_moves(6, 7, board::Player::Yellow, &[2, 6, 3])?;
+        for depth in 2..=6 {
+            let config = SearchConfig {
+                depth,
+                evaluation: Evaluation::Threats(ThreatWeights::default()),
+                ..SearchConfig::default()
+            };
+            let m = search(&game, &config).best_move;
+            assert!(
+                m == Move::Drop(1) || m == Move::Drop(4),
+                "depth {} played {:?}",
+                depth,
+                m
+            );
+        }
+        Ok(())
+    }
+
+    struct Column(usize);
+
+    impl Evaluator for Column {
+        fn evaluate(&self, game: &game::Game, player: board::Player) -> i32 {
+            let board = game.get_board();
+            (0..board.get_rows())
+                .filter(|&row| board.get_piece_at(row, self.0) == Some(player))
+                .count() as i32
+        }
+    }
+
+    #[test]
+    fn search_with_custom_evaluator() {
+        let game = game::Game::new(board::Player::Yellow);
+        let config = SearchConfig {
+            depth: 1,
+            ..SearchConfig::default()
+        };
+        assert_eq!(
+            search_with(&game, &config, &Column(5)).best_move,
+            Move::Drop(5)
+        );
+        assert_eq!(
+            search_with(&game, &config, &Column(0)).best_move,
+            Move::Drop(0)
+        );
+    }
+
+    #[test]
+    fn search_uses_the_configured_evaluator() -> Result<(), board::PlayErr> {
+        let game = game::Game::from_moves(6, 7, board::Player::Yellow, &[3, 3, 2, 4])?;
+        let weights = ThreatWeights::default();
+        let config = SearchConfig {
+            evaluation: Evaluation::Threats(weights),
+            ..SearchConfig::default()
+        };
+        let configured = search(&game, &config);
+        let explicit = search_with(&game, &config, &weights);
+        assert_eq!(configured.best_move, explicit.best_move);
+        assert_eq!(configured.score, explicit.score);
+        assert_eq!(configured.nodes, explicit.nodes);
+        Ok(())
+    }
+
+    #[test]
+    fn searches_for_the_connect_length() -> Result<(), board::PlayErr> {
+        let mut game = game::Game::with_connect(6, 7, 3, board::Player::Yellow);
+        for &col in &[0, 6, 1, 6] {
+            game.play(col)?;
+        }
+        let result = search(&game, &SearchConfig::default());
+
+        assert_eq!(result.best_move, Move::Drop(2));
+        assert_eq!(result.win_in(), Some(1));
+        Ok(())
+    }
+
+    #[test]
+    fn finds_winning_pops() -> Result<(), board::PlayErr> {
+        let mut game =
+            game::Game::with_rules(6, 7, 4, Arc::new(game::PopOut), board::Player::Yellow);
+        for &col in &[3, 3, 3, 0, 0, 1, 1, 6, 2, 6, 2, 6] {
+            game.play(col)?;
+        }
+        let result = search(&game, &SearchConfig::default());
+
+        assert_eq!(result.best_move, Move::Pop(3));
+        assert_eq!(result.win_in(), Some(1));
+        assert_eq!(get_ai_play(&game), Move::Pop(3));
+        Ok(())
+    }
+
+    #[test]
+    fn searches_with_custom_rules() -> Result<(), board::PlayErr> {
+        // Yellow holds columns 1 and 2 of the bottom row. Playing the first
+        // column and then the fourth in a row can't be stopped.
+        let mut game = game::Game::with_rules(
+            6,
+            7,
+            4,
+            Arc::new(game::FirstColumnBonus),
+            board::Player::Yellow,
+        );
+        for &col in &[1, 5, 2, 5] {
+            game.play(col)?;
+        }
+        let config = SearchConfig {
+            depth: 2,
+            ..SearchConfig::default()
+        };
+        let result = search(&game, &config);
+
+        assert_eq!(result.best_move, Move::Drop(0));
+        assert_eq!(result.forced, Some(Forced::Win(2)));
+        assert_eq!(result.pv, vec![Move::Drop(0), Move::Drop(3)]);
+        Ok(())
+    }
+
+    #[test]
+    fn searches_rules_with_a_state() -> Result<(), board::PlayErr> {
+        let rules = Arc::new(game::PopTen { target: 1 });
+        let mut game = game::Game::with_rules(2, 3, 2, rules, board::Player::Yellow);
+        // Only Yellow's piece in the first column is part of a line.
+        game.play_notation("123213")?;
+        let result = search(&game, &SearchConfig::default());
+
+        assert_eq!(result.best_move, Move::Pop(0));
+        assert_eq!(result.forced, Some(Forced::Win(1)));
+        Ok(())
+    }
+
+    #[test]
+    fn rows_are_scored_around_cylinders() -> Result<(), board::PlayErr> {
+        let moves = [5, 3, 6, 3, 0, 2];
+        let flat = game::Game::from_moves(6, 7, board::Player::Yellow, &moves)?;
+        let rules = Arc::new(game::Cylinder::new(game::Classic));
+        let mut cylinder = game::Game::with_rules(6, 7, 4, rules, board::Player::Yellow);
+        for &col in &moves {
+            cylinder.play(col)?;
+        }
+        let weights = EvalWeights::default();
+
+        assert!(
+            score_rows(&cylinder, board::Player::Yellow, &weights)
+                > score_rows(&flat, board::Player::Yellow, &weights)
+        );
+        let result = search(&cylinder, &SearchConfig::default());
+        assert!([Move::Drop(1), Move::Drop(4)].contains(&result.best_move));
+        Ok(())
+    }
 }
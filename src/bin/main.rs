@@ -1,85 +1,429 @@
+use std::env;
 use std::io;
 use std::io::Write;
+use std::process;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
 
 use colored::Colorize;
+use connect4_core::agent;
 use connect4_core::ai;
 use connect4_core::board;
 use connect4_core::game;
+use connect4_core::threats;
 
 const STARTING_PLAYER: board::Player = board::Player::Yellow;
+const ROWS: usize = 6;
+const COLS: usize = 7;
+const CONNECT: usize = 4;
+const THINKING_TIME: Duration = Duration::from_secs(1);
+/// Solving the first few moves takes minutes, so the solver AI searches with
+/// the heuristic AI until this many pieces are on the board, and only plays
+/// perfectly from then on. It can still lose in the opening.
+const SOLVER_FROM_MOVE: usize = agent::DEFAULT_SOLVE_FROM;
+const DEFAULT_DELAY: Duration = Duration::from_millis(500);
+
+/// Who plays each color, `None` being a human at the keyboard.
+struct Players {
+    yellow: Option<Box<dyn agent::Agent>>,
+    red: Option<Box<dyn agent::Agent>>,
+    /// Pause after each AI move when no human plays, so games can be
+    /// followed.
+    delay: Duration,
+}
+
+impl Players {
+    fn get_agent(&mut self, player: board::Player) -> Option<&mut Box<dyn agent::Agent>> {
+        match player {
+            board::Player::Yellow => self.yellow.as_mut(),
+            board::Player::Red => self.red.as_mut(),
+        }
+    }
+
+    fn is_ai(&self, player: board::Player) -> bool {
+        match player {
+            board::Player::Yellow => self.yellow.is_some(),
+            board::Player::Red => self.red.is_some(),
+        }
+    }
+}
+
+const USAGE: &str = "Usage: connect4_cli [options]
+
+Options:
+    --rows <n>          Rows of the board (6)
+    --cols <n>          Columns of the board (7)
+    --connect <n>       Pieces in a row needed to win (4)
+    --pop-out           Let players pop their own pieces from the bottom of
+                        a column, typed as p and the column
+    --cylinder          Wrap rows around, so lines can cross the sides
+    --first <player>    Player moving first, yellow or red (yellow)
+    --ai <side>         Color played by the AI, red, yellow, none or both,
+                        asked for when missing
+    --perfect           Let the AI play perfectly from move 9 using the
+                        solver, on classic 4 in a row games
+    --depth <n>         Search depth of the AI, with no time limit unless
+                        --time is given too
+    --time <ms>         Thinking time of the AI per move (1000)
+    --no-color          Draw pieces as letters
+    --load <position>   Start from a classic game position, as in
+                        \"6x7 7/7/7/7/7/3y3 r -\", which can't be combined
+                        with the flags setting up the game, --rows to --first
+    --help              Show this message";
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum AiSide {
+    Yellow,
+    Red,
+    None,
+    Both,
+}
+
+#[derive(Debug, PartialEq)]
+struct Args {
+    rows: usize,
+    cols: usize,
+    connect: usize,
+    pop_out: bool,
+    cylinder: bool,
+    first: board::Player,
+    ai: Option<AiSide>,
+    perfect: bool,
+    depth: Option<usize>,
+    time: Option<Duration>,
+    color: bool,
+    load: Option<String>,
+    help: bool,
+}
+
+impl Default for Args {
+    fn default() -> Self {
+        Args {
+            rows: ROWS,
+            cols: COLS,
+            connect: CONNECT,
+            pop_out: false,
+            cylinder: false,
+            first: STARTING_PLAYER,
+            ai: None,
+            perfect: false,
+            depth: None,
+            time: None,
+            color: true,
+            load: None,
+            help: false,
+        }
+    }
+}
+
+/// Flags setting up the game, which a position given to `--load` already
+/// does.
+const GAME_FLAGS: [&str; 6] = [
+    "--rows",
+    "--cols",
+    "--connect",
+    "--pop-out",
+    "--cylinder",
+    "--first",
+];
+
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<Args, String> {
+    let mut parsed = Args::default();
+    let mut game_flag = None;
+    while let Some(arg) = args.next() {
+        if GAME_FLAGS.contains(&arg.as_str()) {
+            game_flag = Some(arg.clone());
+        }
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("Missing value for {}", arg))
+        };
+        match arg.as_str() {
+            "--rows" => parsed.rows = parse_size(&value()?)?,
+            "--cols" => parsed.cols = parse_size(&value()?)?,
+            "--connect" => parsed.connect = parse_size(&value()?)?,
+            "--pop-out" => parsed.pop_out = true,
+            "--cylinder" => parsed.cylinder = true,
+            "--first" => {
+                parsed.first = match value()?.as_str() {
+                    "yellow" => board::Player::Yellow,
+                    "red" => board::Player::Red,
+                    v => return Err(format!("Invalid player: {}", v)),
+                }
+            }
+            "--ai" => {
+                parsed.ai = Some(match value()?.as_str() {
+                    "yellow" => AiSide::Yellow,
+                    "red" => AiSide::Red,
+                    "none" => AiSide::None,
+                    "both" => AiSide::Both,
+                    v => return Err(format!("Invalid AI side: {}", v)),
+                })
+            }
+            "--perfect" => parsed.perfect = true,
+            "--depth" => parsed.depth = Some(parse_number(&value()?)?),
+            "--time" => {
+                let ms = parse_number(&value()?)?;
+                parsed.time = Some(Duration::from_millis(ms as u64));
+            }
+            "--no-color" => parsed.color = false,
+            "--load" => parsed.load = Some(value()?),
+            "--help" | "-h" => parsed.help = true,
+            _ => return Err(format!("Unknown argument: {}", arg)),
+        }
+    }
+    if let (Some(flag), Some(_)) = (game_flag, &parsed.load) {
+        return Err(format!("{} can't be combined with --load", flag));
+    }
+    Ok(parsed)
+}
+
+fn parse_number(v: &str) -> Result<usize, String> {
+    v.parse().map_err(|_| format!("Invalid number: {}", v))
+}
+
+fn parse_size(v: &str) -> Result<usize, String> {
+    match parse_number(v)? {
+        0 => Err(String::from("Sizes must be at least one")),
+        n => Ok(n),
+    }
+}
+
+/// Starting game, from `--load` or from an empty board.
+fn initial_game(args: &Args) -> Result<game::Game, String> {
+    let rules: Arc<dyn game::Rules> = match (args.pop_out, args.cylinder) {
+        (true, true) => Arc::new(game::Cylinder::new(game::PopOut)),
+        (true, false) => Arc::new(game::PopOut),
+        (false, true) => Arc::new(game::Cylinder::new(game::Classic)),
+        (false, false) => Arc::new(game::Classic),
+    };
+    match &args.load {
+        Some(position) => position.parse().map_err(|e| format!("{}", e)),
+        None => Ok(game::Game::with_rules(
+            args.rows,
+            args.cols,
+            args.connect,
+            rules,
+            args.first,
+        )),
+    }
+}
 
 fn main() {
-    let ai_player = board::Player::Red;
+    let args = match parse_args(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            process::exit(2);
+        }
+    };
+    if args.help {
+        println!("{}", USAGE);
+        return;
+    }
+    let initial = match initial_game(&args) {
+        Ok(game) => game,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(2);
+        }
+    };
+    if !args.color {
+        colored::control::set_override(false);
+    }
+
+    let config = search_config(&args);
+    let mut players = match args.ai {
+        Some(side) => ai_players(side, &config, args.perfect),
+        None => read_players(&config, args.perfect),
+    };
 
-    let mut game = game::Game::new(STARTING_PLAYER);
+    let mut human = agent::HumanAgent::stdin();
+    let mut game = initial.clone();
     print!("\x1B[2J");
-    display_board(game.get_board(), None);
+    display_board(&game, None);
 
     while game.state == game::GameState::Playing {
-        let msg = if game.current_player == ai_player {
+        let only_ai = players.is_ai(board::Player::Yellow) && players.is_ai(board::Player::Red);
+        let msg = if let Some(ai) = players.get_agent(game.current_player) {
             print!("Thinking...");
             io::stdout().flush().unwrap();
-            let ai_move = ai::get_ai_move(&game);
+            let ai_move = ai.choose_play(&game);
             println!();
+            if only_ai {
+                thread::sleep(players.delay);
+            }
             make_play(&mut game, ai_move)
         } else {
-            match read_input(game.current_player) {
-                Ok(0) => Some(String::from("Invalid move")),
-                Ok(v) => make_play(&mut game, v - 1),
-                Err(e) => Some(String::from(e)),
+            match human.read_command(&game, &turn_prompt(&game)) {
+                Ok(agent::Command::TakeBack) => take_back(&mut game, &players),
+                Ok(agent::Command::Play(m)) => make_play(&mut game, m),
+                Err(e) => {
+                    eprintln!("\n{}", e);
+                    process::exit(1);
+                }
             }
         };
 
         if game.state != game::GameState::Playing {
             print!("\x1B[2J");
-            display_board(game.get_board(), msg);
+            display_board(&game, msg);
             print!("Again? (Y|n): ");
             io::stdout().flush().unwrap();
             let buf = &mut String::new();
             if io::stdin().read_line(buf).is_ok() {
                 match buf.trim() {
-                    "Y" | "y" | "" => game = game::Game::new(STARTING_PLAYER),
+                    "Y" | "y" | "" => game = initial.clone(),
                     _ => (),
                 }
             }
 
             print!("\x1B[2J");
-            display_board(game.get_board(), None);
+            display_board(&game, None);
         } else {
             print!("\x1B[2J");
-            display_board(game.get_board(), msg);
+            display_board(&game, msg);
         }
     }
 }
 
-fn make_play(game: &mut game::Game, m: usize) -> Option<String> {
-    match game.play(m) {
+/// The AI, playing perfectly from [`SOLVER_FROM_MOVE`] on when `perfect`
+/// is set.
+fn ai_agent(config: &ai::SearchConfig, perfect: bool) -> Box<dyn agent::Agent> {
+    if perfect {
+        let mut solver = agent::SolverAgent::default();
+        solver.fallback = config.clone();
+        Box::new(solver)
+    } else {
+        Box::new(agent::MinimaxAgent::new(config.clone()))
+    }
+}
+
+fn ai_players(side: AiSide, config: &ai::SearchConfig, perfect: bool) -> Players {
+    let ai = || Some(ai_agent(config, perfect));
+    let (yellow, red) = match side {
+        AiSide::Yellow => (ai(), None),
+        AiSide::Red => (None, ai()),
+        AiSide::None => (None, None),
+        AiSide::Both => (ai(), ai()),
+    };
+    Players {
+        yellow,
+        red,
+        delay: DEFAULT_DELAY,
+    }
+}
+
+fn read_players(config: &ai::SearchConfig, perfect: bool) -> Players {
+    println!("1) Human vs human");
+    println!("2) Human vs AI");
+    println!("3) AI vs AI");
+    let mut players = Players {
+        yellow: None,
+        red: None,
+        delay: DEFAULT_DELAY,
+    };
+    match prompt("Mode (2): ").as_str() {
+        "1" => (),
+        "3" => {
+            players.yellow = Some(read_ai_settings("Yellow", config, perfect));
+            players.red = Some(read_ai_settings("Red", config, perfect));
+            if let Some(ms) = read_number("Delay between moves in ms (500): ") {
+                players.delay = Duration::from_millis(ms as u64);
+            }
+        }
+        _ => {
+            let question = format!("Perfect play from move {}? (y|N): ", SOLVER_FROM_MOVE + 1);
+            let perfect = perfect || matches!(prompt(&question).as_str(), "Y" | "y");
+            let ai = ai_agent(config, perfect);
+            match prompt("Play as (Y)ellow or (r)ed: ").as_str() {
+                "R" | "r" => players.yellow = Some(ai),
+                _ => players.red = Some(ai),
+            }
+        }
+    }
+    players
+}
+
+/// Depth and thinking time of one side when two AIs play each other. A
+/// depth without a time searches straight to that depth.
+fn read_ai_settings(name: &str, config: &ai::SearchConfig, perfect: bool) -> Box<dyn agent::Agent> {
+    let mut config = config.clone();
+    if let Some(depth) = read_number(&format!("{} AI depth (no limit): ", name)) {
+        config.depth = depth;
+        config.time_limit = None;
+    }
+    if let Some(ms) = read_number(&format!("{} AI time per move in ms (1000): ", name)) {
+        config.time_limit = Some(Duration::from_millis(ms as u64));
+    }
+    ai_agent(&config, perfect)
+}
+
+fn prompt(msg: &str) -> String {
+    print!("{}", msg);
+    io::stdout().flush().unwrap();
+    let buf = &mut String::new();
+    match io::stdin().read_line(buf) {
+        Ok(_) => buf.trim().to_string(),
+        Err(_) => String::new(),
+    }
+}
+
+/// Reads a number, `None` when the answer is empty or not a number.
+fn read_number(msg: &str) -> Option<usize> {
+    prompt(msg).parse().ok()
+}
+
+fn search_config(args: &Args) -> ai::SearchConfig {
+    let time_limit = match (args.depth, args.time) {
+        (_, Some(time)) => Some(time),
+        (Some(_), None) => None,
+        (None, None) => Some(THINKING_TIME),
+    };
+    ai::SearchConfig {
+        depth: args.depth.unwrap_or(usize::MAX),
+        time_limit,
+        evaluation: ai::Evaluation::Threats(threats::ThreatWeights::default()),
+        ..ai::SearchConfig::default()
+    }
+}
+
+fn make_play(game: &mut game::Game, m: board::Move) -> Option<String> {
+    match game.play_move(m) {
         Ok(()) => match game.state {
-            game::GameState::Winner(p) => match p {
-                board::Player::Red => Some(format!("Winner {}", CIRCLE.red())),
-                board::Player::Yellow => Some(format!("Winner {}", CIRCLE.yellow())),
-            },
+            game::GameState::Winner(ref info) => Some(format!("Winner {}", piece(info.player))),
             game::GameState::Tie => Some(String::from("Draw")),
             game::GameState::Playing => None,
         },
         Err(e) => match e {
             board::PlayErr::FullColumn => Some(String::from("Full Column")),
             board::PlayErr::OutOfBounds => Some(String::from("Invalid Move")),
+            board::PlayErr::GameOver => Some(String::from("Game Over")),
+            board::PlayErr::CannotPop => Some(String::from("Cannot Pop")),
         },
     }
 }
 
-fn read_input<'a>(player: board::Player) -> Result<usize, &'a str> {
-    let p = match player {
-        board::Player::Yellow => format!("{}", CIRCLE.yellow()),
-        board::Player::Red => format!("{}", CIRCLE.red()),
-    };
-    print!("{} Turn to play: ", p);
-    io::stdout().flush().unwrap();
-    let buf = &mut String::new();
-    match io::stdin().read_line(buf) {
-        Ok(_) => buf.trim().parse().map_err(|_| "Invalid Play"),
-        Err(_) => Err("Error reading buffer"),
+/// Undoes moves until it is a human's turn again.
+fn take_back(game: &mut game::Game, players: &Players) -> Option<String> {
+    if game.undo().is_none() {
+        return Some(String::from("Nothing to take back"));
+    }
+    while players.is_ai(game.current_player) && game.undo().is_some() {}
+    None
+}
+
+fn turn_prompt(game: &game::Game) -> String {
+    let p = piece(game.current_player);
+    let can_pop = game
+        .get_valid_moves()
+        .iter()
+        .any(|m| matches!(m, board::Move::Pop(_)));
+    if can_pop {
+        format!("{} Turn to play (p<column> to pop, u to take back): ", p)
+    } else {
+        format!("{} Turn to play (u to take back): ", p)
     }
 }
 
@@ -95,8 +439,37 @@ const LEFT_SPLIT: &str = "\u{251c}";
 const RIGHT_SPLIT: &str = "\u{2524}";
 const MIDDLE_SPLIT: &str = "\u{253c}";
 const CIRCLE: &str = "\u{2B24}";
+const STAR: &str = "\u{2605}";
 
-fn display_board(board: &board::Board, msg: Option<String>) {
+/// Pieces are colored circles, or letters when colors are off.
+fn piece(player: board::Player) -> String {
+    let colorize = colored::control::SHOULD_COLORIZE.should_colorize();
+    match (player, colorize) {
+        (board::Player::Yellow, true) => CIRCLE.yellow().to_string(),
+        (board::Player::Red, true) => CIRCLE.red().to_string(),
+        (board::Player::Yellow, false) => String::from("Y"),
+        (board::Player::Red, false) => String::from("R"),
+    }
+}
+
+/// Pieces of a winning line are stars, or letters followed by `*` when
+/// colors are off.
+fn winning_piece(player: board::Player) -> String {
+    let colorize = colored::control::SHOULD_COLORIZE.should_colorize();
+    match (player, colorize) {
+        (board::Player::Yellow, true) => format!("{} ", STAR.yellow().bold()),
+        (board::Player::Red, true) => format!("{} ", STAR.red().bold()),
+        (board::Player::Yellow, false) => String::from("Y*"),
+        (board::Player::Red, false) => String::from("R*"),
+    }
+}
+
+fn display_board(game: &game::Game, msg: Option<String>) {
+    let board = game.get_board();
+    let highlight = match game.state {
+        game::GameState::Winner(ref info) => info.get_cells(),
+        _ => Vec::new(),
+    };
     if let Some(msg) = msg {
         println!("{}", msg);
     }
@@ -105,8 +478,10 @@ fn display_board(board: &board::Board, msg: Option<String>) {
         for col in 0..board.get_columns() {
             match board.get_piece_at(row, col) {
                 None => print!("{}  ", VER_LINE),
-                Some(board::Player::Red) => print!("{}{} ", VER_LINE, CIRCLE.red()),
-                Some(board::Player::Yellow) => print!("{}{} ", VER_LINE, CIRCLE.yellow()),
+                Some(p) if highlight.contains(&(row, col)) => {
+                    print!("{}{}", VER_LINE, winning_piece(p))
+                }
+                Some(p) => print!("{}{} ", VER_LINE, piece(p)),
             }
         }
         print!("{}", VER_LINE);
@@ -161,3 +536,122 @@ fn print_header(board: &board::Board) {
     print!("{}", CORNER_TOP_RIGHT);
     println!();
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, String> {
+        parse_args(args.iter().map(|a| a.to_string()))
+    }
+
+    #[test]
+    fn no_arguments_keep_the_defaults() {
+        assert_eq!(parse(&[]), Ok(Args::default()));
+    }
+
+    #[test]
+    fn parses_every_flag() {
+        let args = parse(&[
+            "--rows",
+            "5",
+            "--cols",
+            "8",
+            "--connect",
+            "5",
+            "--pop-out",
+            "--cylinder",
+            "--first",
+            "red",
+            "--ai",
+            "both",
+            "--perfect",
+            "--depth",
+            "4",
+            "--time",
+            "250",
+            "--no-color",
+        ]);
+        assert_eq!(
+            args,
+            Ok(Args {
+                rows: 5,
+                cols: 8,
+                connect: 5,
+                pop_out: true,
+                cylinder: true,
+                first: board::Player::Red,
+                ai: Some(AiSide::Both),
+                perfect: true,
+                depth: Some(4),
+                time: Some(Duration::from_millis(250)),
+                color: false,
+                load: None,
+                help: false,
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_bad_arguments() {
+        assert!(parse(&["--rows"]).is_err());
+        assert!(parse(&["--rows", "0"]).is_err());
+        assert!(parse(&["--connect", "0"]).is_err());
+        assert!(parse(&["--ai", "blue"]).is_err());
+        assert!(parse(&["--first", "green"]).is_err());
+        assert!(parse(&["--fast"]).is_err());
+    }
+
+    #[test]
+    fn depth_alone_has_no_time_limit() -> Result<(), String> {
+        let config = search_config(&parse(&["--depth", "3"])?);
+        assert_eq!((config.depth, config.time_limit), (3, None));
+
+        let config = search_config(&parse(&[])?);
+        assert_eq!(config.time_limit, Some(THINKING_TIME));
+        Ok(())
+    }
+
+    #[test]
+    fn loads_positions() -> Result<(), String> {
+        let game = initial_game(&parse(&["--load", "6x7 7/7/7/7/7/3y3 r -"])?)?;
+        assert_eq!(game.current_player, board::Player::Red);
+        assert!(initial_game(&parse(&["--load", "6x7"])?).is_err());
+
+        for flag in &["--rows", "--cols", "--connect", "--first"] {
+            let value = if *flag == "--first" { "red" } else { "5" };
+            let args = parse(&["--load", "4x4 4/4/4/4 y -", flag, value]);
+            assert_eq!(args, Err(format!("{} can't be combined with --load", flag)));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn pop_out_starts_a_pop_out_game() -> Result<(), String> {
+        let game = initial_game(&parse(&["--pop-out"])?)?;
+        assert_eq!(game.get_rules().name(), "pop out");
+        assert!(parse(&["--pop-out", "--load", "1x4 4 y -"]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn cylinder_starts_a_game_on_a_cylinder() -> Result<(), String> {
+        let game = initial_game(&parse(&["--cylinder", "--pop-out"])?)?;
+        assert!(game.get_board().is_cylinder());
+        assert_eq!(game.get_rules().name(), "cylinder pop out");
+        assert!(!initial_game(&parse(&[])?)?.get_board().is_cylinder());
+        assert!(parse(&["--cylinder", "--load", "1x4 4 y -"]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn perfect_ai_plays_the_winning_move() -> Result<(), board::PlayErr> {
+        let game = game::Game::from_moves(6, 7, board::Player::Yellow, &[0, 6, 1, 6, 2, 5])?;
+        let config = ai::SearchConfig::default();
+        let mut players = ai_players(AiSide::Yellow, &config, true);
+        let ai = players.get_agent(board::Player::Yellow).unwrap();
+        assert_eq!(ai.choose_play(&game), board::Move::Drop(3));
+        assert!(!players.is_ai(board::Player::Red));
+        Ok(())
+    }
+}
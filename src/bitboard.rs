@@ -0,0 +1,273 @@
+use super::board::Player;
+
+/// Bit level representation of a board, used to make move generation and
+/// win detection constant time operations.
+///
+/// Each column takes `rows + 1` consecutive bits, starting from the bottom
+/// row. The extra bit on top of each column is always empty and keeps
+/// horizontal and diagonal shifts from leaking into the next column, so a
+/// board only fits when `cols * (rows + 1) <= 64`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BitBoard {
+    rows: usize,
+    cols: usize,
+    pieces: [u64; 2],
+    height: u64,
+}
+
+impl BitBoard {
+    pub fn fits(rows: usize, cols: usize) -> bool {
+        rows > 0 && cols > 0 && cols * (rows + 1) <= 64
+    }
+
+    pub fn new(rows: usize, cols: usize) -> Option<Self> {
+        if !BitBoard::fits(rows, cols) {
+            return None;
+        }
+        let height = (0..cols).fold(0, |mask, c| mask | 1 << (c * (rows + 1)));
+        Some(BitBoard {
+            rows,
+            cols,
+            pieces: [0, 0],
+            height,
+        })
+    }
+
+    pub fn can_play(&self, col: usize) -> bool {
+        col < self.cols && self.height & self.top_mask(col) == 0
+    }
+
+    /// Drops a piece in `col`, returning the row it landed on counted from
+    /// the bottom of the board.
+    pub fn play(&mut self, col: usize, player: Player) -> Option<usize> {
+        if !self.can_play(col) {
+            return None;
+        }
+        let bit = self.height & self.column_mask(col);
+        self.pieces[player.index()] |= bit;
+        self.height += bit;
+        Some(bit.trailing_zeros() as usize - col * (self.rows + 1))
+    }
+
+    /// Removes the topmost piece of `col`, returning the row it was on
+    /// counted from the bottom of the board.
+    pub fn undo(&mut self, col: usize) -> Option<usize> {
+        if col >= self.cols {
+            return None;
+        }
+        let column = self.column_mask(col) | self.top_mask(col);
+        let bit = self.height & column;
+        if bit & self.bottom_mask(col) != 0 {
+            return None;
+        }
+        let top = bit >> 1;
+        self.pieces[0] &= !top;
+        self.pieces[1] &= !top;
+        self.height -= top;
+        Some(top.trailing_zeros() as usize - col * (self.rows + 1))
+    }
+
+    /// Removes the bottom piece of `col`, shifting the rest of the column
+    /// down, and returns the player it belonged to.
+    pub fn pop(&mut self, col: usize) -> Option<Player> {
+        if col >= self.cols {
+            return None;
+        }
+        let player = self.get(0, col)?;
+        let column = self.column_mask(col);
+        for pieces in self.pieces.iter_mut() {
+            *pieces = (*pieces & !column) | ((*pieces & column) >> 1 & column);
+        }
+        let top = self.height & (column | self.top_mask(col));
+        self.height -= top >> 1;
+        Some(player)
+    }
+
+    /// Puts a piece of `player` back at the bottom of `col`, shifting the
+    /// rest of the column up, which undoes [`BitBoard::pop`]. Returns
+    /// whether the column had room for it.
+    pub fn unpop(&mut self, col: usize, player: Player) -> bool {
+        if !self.can_play(col) {
+            return false;
+        }
+        let column = self.column_mask(col);
+        for pieces in self.pieces.iter_mut() {
+            *pieces = (*pieces & !column) | ((*pieces & column) << 1);
+        }
+        self.pieces[player.index()] |= self.bottom_mask(col);
+        self.height += self.height & column;
+        true
+    }
+
+    pub fn get(&self, row: usize, col: usize) -> Option<Player> {
+        if row >= self.rows || col >= self.cols {
+            return None;
+        }
+        let bit = 1 << (col * (self.rows + 1) + row);
+        if self.pieces[0] & bit != 0 {
+            Some(Player::Yellow)
+        } else if self.pieces[1] & bit != 0 {
+            Some(Player::Red)
+        } else {
+            None
+        }
+    }
+
+    pub fn get_mask(&self) -> u64 {
+        self.pieces[0] | self.pieces[1]
+    }
+
+    pub fn get_player_mask(&self, player: Player) -> u64 {
+        self.pieces[player.index()]
+    }
+
+    /// Whether `player` has `length` pieces in a row in any direction. Lines
+    /// can't wrap between columns, since any line long enough to do so goes
+    /// through the empty bit on top of a column.
+    pub fn has_line(&self, player: Player, length: usize) -> bool {
+        let m = self.pieces[player.index()];
+        let h = self.rows + 1;
+        for &dir in &[1, h, h - 1, h + 1] {
+            let line = (1..length).fold(m, |line, i| {
+                line & m.checked_shr((i * dir) as u32).unwrap_or(0)
+            });
+            if line != 0 {
+                return true;
+            }
+        }
+        false
+    }
+
+    pub fn get_winner(&self, length: usize) -> Option<Player> {
+        if self.has_line(Player::Yellow, length) {
+            Some(Player::Yellow)
+        } else if self.has_line(Player::Red, length) {
+            Some(Player::Red)
+        } else {
+            None
+        }
+    }
+
+    fn column_mask(&self, col: usize) -> u64 {
+        ((1 << self.rows) - 1) << (col * (self.rows + 1))
+    }
+
+    fn bottom_mask(&self, col: usize) -> u64 {
+        1 << (col * (self.rows + 1))
+    }
+
+    fn top_mask(&self, col: usize) -> u64 {
+        1 << (col * (self.rows + 1) + self.rows)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn only_small_boards_fit() {
+        assert!(BitBoard::fits(6, 7));
+        assert!(BitBoard::fits(7, 8));
+        assert!(!BitBoard::fits(8, 8));
+        assert_eq!(BitBoard::new(10, 10), None);
+    }
+
+    #[test]
+    fn play_stacks_pieces_until_column_is_full() {
+        let mut bits = BitBoard::new(3, 3).expect("3x3 fits");
+        assert_eq!(bits.play(1, Player::Yellow), Some(0));
+        assert_eq!(bits.play(1, Player::Red), Some(1));
+        assert_eq!(bits.play(1, Player::Yellow), Some(2));
+        assert!(!bits.can_play(1));
+        assert_eq!(bits.play(1, Player::Red), None);
+        assert_eq!(bits.get(1, 1), Some(Player::Red));
+        assert_eq!(bits.get(0, 0), None);
+    }
+
+    #[test]
+    fn undo_restores_previous_position() {
+        let mut bits = BitBoard::new(3, 3).expect("3x3 fits");
+        let empty = bits.clone();
+        bits.play(2, Player::Yellow);
+        let one_piece = bits.clone();
+        bits.play(2, Player::Red);
+
+        assert_eq!(bits.undo(2), Some(1));
+        assert_eq!(bits, one_piece);
+        assert_eq!(bits.undo(2), Some(0));
+        assert_eq!(bits, empty);
+        assert_eq!(bits.undo(2), None);
+    }
+
+    #[test]
+    fn pop_shifts_the_column_down() {
+        let mut bits = BitBoard::new(3, 3).expect("3x3 fits");
+        bits.play(0, Player::Red);
+        bits.play(1, Player::Yellow);
+        bits.play(1, Player::Red);
+        bits.play(2, Player::Yellow);
+        let before = bits.clone();
+
+        assert_eq!(bits.pop(1), Some(Player::Yellow));
+        assert_eq!(bits.get(0, 1), Some(Player::Red));
+        assert_eq!(bits.get(1, 1), None);
+        assert_eq!(bits.get(0, 0), Some(Player::Red));
+        assert_eq!(bits.get(0, 2), Some(Player::Yellow));
+        assert_eq!(bits.play(1, Player::Yellow), Some(1));
+        bits.undo(1);
+
+        assert!(bits.unpop(1, Player::Yellow));
+        assert_eq!(bits, before);
+        assert_eq!(BitBoard::new(3, 3).and_then(|mut b| b.pop(0)), None);
+    }
+
+    #[test]
+    fn detects_four_in_every_direction() {
+        let mut vertical = BitBoard::new(6, 7).expect("6x7 fits");
+        let mut horizontal = vertical.clone();
+        let mut diagonal = vertical.clone();
+        for _ in 0..4 {
+            vertical.play(6, Player::Red);
+        }
+        for c in 3..7 {
+            horizontal.play(c, Player::Red);
+        }
+        for c in 0..4 {
+            for _ in 0..c {
+                diagonal.play(c, Player::Yellow);
+            }
+            diagonal.play(c, Player::Red);
+        }
+
+        assert_eq!(vertical.get_winner(4), Some(Player::Red));
+        assert_eq!(horizontal.get_winner(4), Some(Player::Red));
+        assert_eq!(diagonal.get_winner(4), Some(Player::Red));
+        assert_eq!(diagonal.get_winner(5), None);
+    }
+
+    #[test]
+    fn detects_lines_of_any_length() {
+        let mut bits = BitBoard::new(6, 7).expect("6x7 fits");
+        for c in 1..6 {
+            bits.play(c, Player::Yellow);
+        }
+
+        assert!(bits.has_line(Player::Yellow, 5));
+        assert!(!bits.has_line(Player::Yellow, 6));
+        assert!(bits.has_line(Player::Yellow, 1));
+        assert!(!bits.has_line(Player::Red, 1));
+    }
+
+    #[test]
+    fn pieces_do_not_wrap_between_columns() {
+        let mut bits = BitBoard::new(3, 7).expect("3x7 fits");
+        for c in 0..2 {
+            for _ in 0..3 {
+                bits.play(c, Player::Yellow);
+            }
+        }
+
+        assert_eq!(bits.get_winner(4), None);
+    }
+}
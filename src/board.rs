@@ -1,16 +1,28 @@
+use super::bitboard::BitBoard;
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Board {
     rows: usize,
     cols: usize,
     pieces: Vec<Option<Player>>,
+    heights: Vec<usize>,
+    bits: Option<BitBoard>,
+    moves: Vec<(Move, Player)>,
+    hash: u64,
     played: usize,
-    last_move: Option<usize>,
+    last_move: Option<Move>,
+    cylinder: bool,
 }
 
 #[derive(Debug, PartialEq)]
 pub enum PlayErr {
     FullColumn,
     OutOfBounds,
+    /// The game already has a winner or ended in a tie.
+    GameOver,
+    /// Pops aren't allowed, or the bottom piece of the column isn't one of
+    /// the player's.
+    CannotPop,
 }
 
 #[derive(Debug, PartialEq, Clone, Copy)]
@@ -19,6 +31,53 @@ pub enum Player {
     Red,
 }
 
+impl Player {
+    pub fn opponent(self) -> Player {
+        match self {
+            Player::Yellow => Player::Red,
+            Player::Red => Player::Yellow,
+        }
+    }
+
+    /// Index of the player in arrays holding something for each of them.
+    pub(crate) fn index(self) -> usize {
+        match self {
+            Player::Yellow => 0,
+            Player::Red => 1,
+        }
+    }
+}
+
+/// A move of a player, either dropping a piece on top of a column or, in
+/// the Pop Out variant, removing one of the player's own pieces from the
+/// bottom of a column.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum Move {
+    Drop(usize),
+    Pop(usize),
+}
+
+impl Move {
+    pub fn get_column(self) -> usize {
+        match self {
+            Move::Drop(col) | Move::Pop(col) => col,
+        }
+    }
+
+    /// Column of a move that has to be a drop, for functions returning the
+    /// column to drop a piece in.
+    ///
+    /// # Panics
+    ///
+    /// When the move is not a drop.
+    pub(crate) fn expect_drop(self) -> usize {
+        match self {
+            Move::Drop(col) => col,
+            m => panic!("{:?} is not a drop", m),
+        }
+    }
+}
+
 impl Board {
     pub fn new(rows: usize, cols: usize) -> Self {
         let pieces = (0..cols * rows).map(|_| None).collect();
@@ -26,9 +85,43 @@ impl Board {
             rows,
             cols,
             pieces,
+            heights: vec![0; cols],
+            bits: BitBoard::new(rows, cols),
+            moves: Vec::with_capacity(rows * cols),
+            hash: 0,
             played: 0,
             last_move: None,
+            cylinder: false,
+        }
+    }
+
+    /// Builds a board whose rows wrap around, the last column being next
+    /// to the first. Lines crossing the edge can't be found with shifts,
+    /// so these boards have no bitboard.
+    pub fn cylinder(rows: usize, cols: usize) -> Self {
+        Board {
+            bits: None,
+            cylinder: true,
+            ..Board::new(rows, cols)
+        }
+    }
+
+    /// Builds a board from its pieces listed row by row from the top. The
+    /// pieces must already be stacked from the bottom of each column, and
+    /// the resulting board has no moves to undo.
+    pub(crate) fn from_pieces(rows: usize, cols: usize, pieces: Vec<Option<Player>>) -> Self {
+        let mut board = Board::new(rows, cols);
+        for col in 0..cols {
+            for row in (0..rows).rev() {
+                match pieces[row * cols + col] {
+                    Some(p) => board.play(col, p).expect("pieces should fit in the board"),
+                    None => break,
+                }
+            }
         }
+        board.moves.clear();
+        board.last_move = None;
+        board
     }
 
     pub fn play(&mut self, col: usize, p: Player) -> Result<(), PlayErr> {
@@ -39,23 +132,98 @@ impl Board {
         match first_not_empty {
             Some(n) => {
                 self.set_piece_at(n, col, p);
+                self.heights[col] += 1;
+                if let Some(bits) = &mut self.bits {
+                    bits.play(col, p);
+                }
+                self.moves.push((Move::Drop(col), p));
                 self.played += 1;
-                self.last_move = Some(col);
+                self.last_move = Some(Move::Drop(col));
                 Ok(())
             }
             None => Err(PlayErr::FullColumn),
         }
     }
 
-    pub fn get_valid_moves(&self) -> Vec<usize> {
-        let mut ret = Vec::new();
-        for c in 0..self.cols {
-            if self.find_empty_row_in_column(c).is_some() {
-                ret.push(c);
+    /// Removes the bottom piece of `col`, which must belong to `p`, and
+    /// shifts the pieces above it down.
+    pub fn pop(&mut self, col: usize, p: Player) -> Result<(), PlayErr> {
+        if col >= self.cols {
+            return Err(PlayErr::OutOfBounds);
+        }
+        if self.get_piece_at(self.rows - 1, col) != Some(p) {
+            return Err(PlayErr::CannotPop);
+        }
+        let column = self.take_column(col);
+        self.put_column(col, &column[1..]);
+        self.heights[col] -= 1;
+        if let Some(bits) = &mut self.bits {
+            bits.pop(col);
+        }
+        self.moves.push((Move::Pop(col), p));
+        self.played -= 1;
+        self.last_move = Some(Move::Pop(col));
+        Ok(())
+    }
+
+    pub fn play_move(&mut self, m: Move, p: Player) -> Result<(), PlayErr> {
+        match m {
+            Move::Drop(col) => self.play(col, p),
+            Move::Pop(col) => self.pop(col, p),
+        }
+    }
+
+    /// Takes back the last move, returning its column, or `None` if there
+    /// are no moves to take back. See [`Board::undo_play`] to tell drops
+    /// from pops.
+    pub fn undo(&mut self) -> Option<usize> {
+        self.undo_play().map(|m| m.get_column())
+    }
+
+    /// Takes back the last move, returning it, or `None` if there are no
+    /// moves to take back.
+    pub fn undo_play(&mut self) -> Option<Move> {
+        let (m, player) = self.moves.pop()?;
+        match m {
+            Move::Drop(col) => {
+                let row = self.rows - self.heights[col];
+                let idx = self.calc_index(row, col);
+                if let Some(p) = self.pieces[idx].take() {
+                    self.hash ^= zobrist_key(idx, p);
+                }
+                self.heights[col] -= 1;
+                if let Some(bits) = &mut self.bits {
+                    bits.undo(col);
+                }
+                self.played -= 1;
+            }
+            Move::Pop(col) => {
+                let mut column = vec![player];
+                column.extend(self.take_column(col));
+                self.put_column(col, &column);
+                self.heights[col] += 1;
+                if let Some(bits) = &mut self.bits {
+                    bits.unpop(col, player);
+                }
+                self.played += 1;
             }
         }
+        self.last_move = self.moves.last().map(|&(m, _)| m);
+        Some(m)
+    }
 
-        ret
+    /// Columns that aren't full.
+    pub fn get_valid_moves(&self) -> Vec<usize> {
+        (0..self.cols)
+            .filter(|&c| self.heights[c] < self.rows)
+            .collect()
+    }
+
+    /// Columns with a piece of `player` at the bottom.
+    pub fn get_valid_pops(&self, player: Player) -> Vec<usize> {
+        (0..self.cols)
+            .filter(|&c| self.get_piece_at(self.rows - 1, c) == Some(player))
+            .collect()
     }
 
     pub fn is_board_full(&self) -> bool {
@@ -82,31 +250,84 @@ impl Board {
         self.rows
     }
 
+    /// Number of pieces on the board, which is the number of moves made
+    /// unless some were popped.
     pub fn get_moves(&self) -> usize {
         self.played
     }
 
+    /// Number of moves made on the board, pops included, which is how many
+    /// [`Board::undo`] can take back.
+    pub fn get_plays(&self) -> usize {
+        self.moves.len()
+    }
+
+    /// Number of pieces in `col`.
+    pub fn get_height(&self, col: usize) -> usize {
+        self.heights.get(col).copied().unwrap_or(0)
+    }
+
+    /// Column of the last move, whether a piece was dropped in it or
+    /// popped from it. See [`Board::get_last_play`] to tell them apart.
     pub fn get_last_move(&self) -> Option<usize> {
+        self.last_move.map(|m| m.get_column())
+    }
+
+    pub fn get_last_play(&self) -> Option<Move> {
         self.last_move
     }
 
+    /// Zobrist hash of the pieces on the board. Boards with the same pieces
+    /// have the same hash, regardless of the order the moves were made.
+    pub fn get_hash(&self) -> u64 {
+        self.hash
+    }
+
+    /// Whether rows wrap around, see [`Board::cylinder`].
+    pub fn is_cylinder(&self) -> bool {
+        self.cylinder
+    }
+
+    /// Bitboard mirror of the pieces, only available when the board is small
+    /// enough to fit in a `u64`.
+    pub fn get_bitboard(&self) -> Option<&BitBoard> {
+        self.bits.as_ref()
+    }
+
     fn find_empty_row_in_column(&self, col: usize) -> Option<usize> {
-        let row = self
-            .pieces
-            .iter()
-            .skip(col)
-            .step_by(self.cols)
-            .position(|p| *p != None)
-            .or(Some(self.rows));
+        match self.heights[col] {
+            h if h >= self.rows => None,
+            h => Some(self.rows - h - 1),
+        }
+    }
+
+    /// Removes the pieces of `col`, returning them from the bottom up.
+    fn take_column(&mut self, col: usize) -> Vec<Player> {
+        let mut column = Vec::with_capacity(self.heights[col]);
+        for row in (0..self.rows).rev() {
+            let idx = self.calc_index(row, col);
+            match self.pieces[idx].take() {
+                Some(p) => {
+                    self.hash ^= zobrist_key(idx, p);
+                    column.push(p);
+                }
+                None => break,
+            }
+        }
+        column
+    }
 
-        match row {
-            Some(0) | None => None,
-            Some(n) => Some(n - 1),
+    /// Stacks `pieces`, listed from the bottom up, in the emptied `col`.
+    fn put_column(&mut self, col: usize, pieces: &[Player]) {
+        for (i, &p) in pieces.iter().enumerate() {
+            self.set_piece_at(self.rows - 1 - i, col, p);
         }
     }
 
     fn set_piece_at(&mut self, row: usize, col: usize, player: Player) {
-        self.pieces[row * self.cols + col] = Some(player);
+        let idx = self.calc_index(row, col);
+        self.pieces[idx] = Some(player);
+        self.hash ^= zobrist_key(idx, player);
     }
 
     fn calc_index(&self, row: usize, col: usize) -> usize {
@@ -114,6 +335,19 @@ impl Board {
     }
 }
 
+/// Pseudo random key for a piece of `player` at cell `index`, derived with
+/// splitmix64 so any board size gets keys without a precomputed table.
+fn zobrist_key(index: usize, player: Player) -> u64 {
+    let seed = match player {
+        Player::Yellow => index as u64 * 2,
+        Player::Red => index as u64 * 2 + 1,
+    };
+    let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
 #[cfg(test)]
 mod test {
 
@@ -142,21 +376,24 @@ mod test {
     }
 
     #[test]
-    fn find_row_when_searching_for_non_empty_col_in_a_empty_col() {
+    fn find_row_when_searching_for_non_empty_col_in_a_empty_col() -> Result<(), PlayErr> {
         let mut board = Board::new(ROWS, COLS);
-        board.pieces[target_index(ROWS - 1, 0)] = Some(Player::Red);
+        board.play(0, Player::Red)?;
+        assert_eq!(board.pieces[target_index(ROWS - 1, 0)], Some(Player::Red));
         let found_row = board.find_empty_row_in_column(0);
         assert_eq!(found_row, Some(ROWS - 2));
+        Ok(())
     }
 
     #[test]
-    fn find_row_when_searching_for_full_col_in_a_empty_col() {
+    fn find_row_when_searching_for_full_col_in_a_empty_col() -> Result<(), PlayErr> {
         let mut board = Board::new(ROWS, COLS);
-        for r in 0..ROWS {
-            board.pieces[target_index(r, 0)] = Some(Player::Red);
+        for _ in 0..ROWS {
+            board.play(0, Player::Red)?;
         }
         let found_row = board.find_empty_row_in_column(0);
         assert_eq!(found_row, None);
+        Ok(())
     }
 
     #[test]
@@ -164,12 +401,12 @@ mod test {
         let mut board = Board::new(ROWS, COLS);
         for c in 0..COLS {
             for _ in 0..ROWS {
-                assert_eq!(board.is_board_full(), false);
+                assert!(!board.is_board_full());
                 board.play(c, Player::Yellow)?;
             }
         }
 
-        assert_eq!(board.is_board_full(), true);
+        assert!(board.is_board_full());
         Ok(())
     }
 
@@ -214,10 +451,142 @@ mod test {
         board
             .play(0, Player::Red)
             .expect("col 0 in a empty board is valid");
-        assert_eq!(board.last_move, Some(0));
+        assert_eq!(board.last_move, Some(Move::Drop(0)));
         board
             .play(1, Player::Red)
             .expect("col 1 in a empty board is valid");
-        assert_eq!(board.last_move, Some(1));
+        assert_eq!(board.last_move, Some(Move::Drop(1)));
+    }
+
+    #[test]
+    fn undo_restores_board() -> Result<(), PlayErr> {
+        let mut board = Board::new(ROWS, COLS);
+        board.play(3, Player::Yellow)?;
+        let after_first = board.clone();
+        board.play(3, Player::Red)?;
+        board.play(1, Player::Yellow)?;
+
+        assert_eq!(board.undo(), Some(1));
+        assert_eq!(board.get_last_move(), Some(3));
+        assert_eq!(board.undo(), Some(3));
+        assert_eq!(board, after_first);
+        assert_eq!(board.undo(), Some(3));
+        assert_eq!(board, Board::new(ROWS, COLS));
+        assert_eq!(board.undo(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn undo_reopens_full_column() -> Result<(), PlayErr> {
+        let mut board = Board::new(ROWS, COLS);
+        for _ in 0..ROWS {
+            board.play(0, Player::Red)?;
+        }
+        assert_eq!(board.play(0, Player::Red), Err(PlayErr::FullColumn));
+        board.undo();
+        assert!(board.get_valid_moves().contains(&0));
+        board.play(0, Player::Yellow)?;
+        assert_eq!(board.get_piece_at(0, 0), Some(Player::Yellow));
+        Ok(())
+    }
+
+    #[test]
+    fn hash_ignores_move_order() -> Result<(), PlayErr> {
+        let mut a = Board::new(ROWS, COLS);
+        a.play(0, Player::Yellow)?;
+        a.play(1, Player::Red)?;
+        a.play(2, Player::Yellow)?;
+        let mut b = Board::new(ROWS, COLS);
+        b.play(2, Player::Yellow)?;
+        b.play(1, Player::Red)?;
+        b.play(0, Player::Yellow)?;
+        let mut c = Board::new(ROWS, COLS);
+        c.play(0, Player::Red)?;
+        c.play(1, Player::Yellow)?;
+        c.play(2, Player::Yellow)?;
+
+        assert_eq!(a.get_hash(), b.get_hash());
+        assert_ne!(a.get_hash(), c.get_hash());
+        Ok(())
+    }
+
+    #[test]
+    fn undo_restores_hash() -> Result<(), PlayErr> {
+        let mut board = Board::new(ROWS, COLS);
+        board.play(4, Player::Yellow)?;
+        let hash = board.get_hash();
+        board.play(4, Player::Red)?;
+        assert_ne!(board.get_hash(), hash);
+        board.undo();
+        assert_eq!(board.get_hash(), hash);
+        board.undo();
+        assert_eq!(board.get_hash(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn bitboard_mirrors_pieces() -> Result<(), PlayErr> {
+        let mut board = Board::new(ROWS, COLS);
+        board.play(2, Player::Red)?;
+        board.play(2, Player::Yellow)?;
+        board.play(4, Player::Red)?;
+
+        let bits = board.get_bitboard().expect("6x6 board fits in a bitboard");
+        for row in 0..ROWS {
+            for col in 0..COLS {
+                assert_eq!(bits.get(ROWS - row - 1, col), board.get_piece_at(row, col));
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn large_boards_have_no_bitboard() -> Result<(), PlayErr> {
+        let mut board = Board::new(10, 10);
+        assert_eq!(board.get_bitboard(), None);
+        board.play(9, Player::Red)?;
+        assert_eq!(board.get_piece_at(9, 9), Some(Player::Red));
+        assert_eq!(board.get_valid_moves().len(), 10);
+        Ok(())
+    }
+
+    #[test]
+    fn pop_shifts_the_column_down() -> Result<(), PlayErr> {
+        let mut board = Board::new(ROWS, COLS);
+        board.play(2, Player::Yellow)?;
+        board.play(2, Player::Red)?;
+        board.play(2, Player::Red)?;
+
+        assert_eq!(board.pop(2, Player::Red), Err(PlayErr::CannotPop));
+        assert_eq!(board.pop(3, Player::Yellow), Err(PlayErr::CannotPop));
+        board.pop(2, Player::Yellow)?;
+        assert_eq!(board.get_piece_at(ROWS - 1, 2), Some(Player::Red));
+        assert_eq!(board.get_piece_at(ROWS - 2, 2), Some(Player::Red));
+        assert_eq!(board.get_piece_at(ROWS - 3, 2), None);
+        assert_eq!(board.get_height(2), 2);
+        assert_eq!(board.get_moves(), 2);
+        assert_eq!(board.get_last_play(), Some(Move::Pop(2)));
+        assert_eq!(board.get_valid_pops(Player::Red), vec![2]);
+        assert_eq!(board.get_valid_pops(Player::Yellow), vec![]);
+
+        let mut same = Board::new(ROWS, COLS);
+        same.play(2, Player::Red)?;
+        same.play(2, Player::Red)?;
+        assert_eq!(board.get_hash(), same.get_hash());
+        assert_eq!(board.get_bitboard(), same.get_bitboard());
+        Ok(())
+    }
+
+    #[test]
+    fn undo_puts_popped_pieces_back() -> Result<(), PlayErr> {
+        let mut board = Board::new(ROWS, COLS);
+        board.play(0, Player::Yellow)?;
+        board.play(0, Player::Red)?;
+        let before = board.clone();
+        board.play_move(Move::Pop(0), Player::Yellow)?;
+
+        assert_eq!(board.undo_play(), Some(Move::Pop(0)));
+        assert_eq!(board, before);
+        Ok(())
     }
 }
@@ -16,11 +16,42 @@ macro_rules! create_board_iterator {
     };
 }
 
-create_board_iterator!(RowIterator);
 create_board_iterator!(ColIterator);
 create_board_iterator!(MainDiagonalIterator);
 create_board_iterator!(SecondaryDiagonalIterator);
 
+pub struct RowIterator<'a> {
+    board: &'a board::Board,
+    pos: usize,
+    wrap: usize,
+}
+
+impl<'a> RowIterator<'a> {
+    pub fn new(board: &'a board::Board) -> Self {
+        RowIterator {
+            board,
+            pos: 0,
+            wrap: 0,
+        }
+    }
+
+    /// Rows followed, on a cylinder, by the cells a line of `len` pieces
+    /// can wrap around to, so each line of `len` cells of a row is one of
+    /// its windows, and only one.
+    pub fn wrapping(board: &'a board::Board, len: usize) -> Self {
+        let wrap = if board.is_cylinder() && len < board.get_columns() {
+            len.saturating_sub(1)
+        } else {
+            0
+        };
+        RowIterator {
+            board,
+            pos: 0,
+            wrap,
+        }
+    }
+}
+
 impl<'a> Iterator for RowIterator<'a> {
     type Item = Vec<&'a Option<board::Player>>;
 
@@ -28,13 +59,13 @@ impl<'a> Iterator for RowIterator<'a> {
         if self.pos >= self.board.get_rows() {
             return None;
         }
-        let ret = self
+        let row = self
             .board
             .get_pieces()
             .iter()
             .skip(self.pos * self.board.get_columns())
-            .take(self.board.get_columns())
-            .collect();
+            .take(self.board.get_columns());
+        let ret = row.clone().chain(row.take(self.wrap)).collect();
         self.pos += 1;
         Some(ret)
     }
@@ -63,6 +94,11 @@ impl<'a> Iterator for MainDiagonalIterator<'a> {
     type Item = Vec<&'a Option<board::Player>>;
 
     fn next(&mut self) -> Option<Self::Item> {
+        if self.board.is_cylinder() {
+            let ret = cylinder_diagonal(self.board, self.pos, 1)?;
+            self.pos += 1;
+            return Some(ret);
+        }
         let diag_n = self.board.get_rows() + self.board.get_columns() - 1;
         if self.pos >= diag_n {
             return None;
@@ -94,6 +130,11 @@ impl<'a> Iterator for SecondaryDiagonalIterator<'a> {
     type Item = Vec<&'a Option<board::Player>>;
 
     fn next(&mut self) -> Option<Self::Item> {
+        if self.board.is_cylinder() {
+            let ret = cylinder_diagonal(self.board, self.pos, -1)?;
+            self.pos += 1;
+            return Some(ret);
+        }
         let diag_n = self.board.get_rows() + self.board.get_columns() - 1;
         if self.pos >= diag_n {
             return None;
@@ -121,6 +162,27 @@ impl<'a> Iterator for SecondaryDiagonalIterator<'a> {
     }
 }
 
+/// On a cylinder diagonals never reach a side, so each one goes from the
+/// top row, at column `pos`, to the bottom row, moving `step` columns on
+/// every row.
+fn cylinder_diagonal(
+    board: &board::Board,
+    pos: usize,
+    step: isize,
+) -> Option<Vec<&Option<board::Player>>> {
+    let cols = board.get_columns();
+    if pos >= cols {
+        return None;
+    }
+    let ret = (0..board.get_rows())
+        .map(|row| {
+            let col = (pos as isize + row as isize * step).rem_euclid(cols as isize) as usize;
+            &board.get_pieces()[row * cols + col]
+        })
+        .collect();
+    Some(ret)
+}
+
 #[cfg(test)]
 mod test {
     const ROWS: usize = 3;
@@ -135,7 +197,7 @@ mod test {
         board.play(0, Player::Yellow)?;
         board.play(1, Player::Yellow)?;
 
-        let it = RowIterator::new(&board);
+        let it = RowIterator::new(board);
         let expected_it = vec![
             vec![&Some(Player::Yellow), &None, &None],
             vec![&Some(Player::Yellow), &None, &None],
@@ -155,7 +217,7 @@ mod test {
         board.play(0, Player::Yellow)?;
         board.play(1, Player::Yellow)?;
 
-        let it = ColIterator::new(&board);
+        let it = ColIterator::new(board);
         let expected_it = vec![
             vec![
                 &Some(Player::Yellow),
@@ -180,8 +242,8 @@ mod test {
         board.play(0, Player::Yellow)?;
         board.play(1, Player::Yellow)?;
 
-        let d1_it = MainDiagonalIterator::new(&board);
-        let d2_it = SecondaryDiagonalIterator::new(&board);
+        let d1_it = MainDiagonalIterator::new(board);
+        let d2_it = SecondaryDiagonalIterator::new(board);
         let mut it = d1_it.chain(d2_it);
         let mut expected_it = vec![
             vec![&Some(Player::Yellow)],
@@ -208,4 +270,62 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn rows_wrap_around_cylinders() -> Result<(), board::PlayErr> {
+        let board = &mut board::Board::cylinder(1, 4);
+        board.play(0, Player::Yellow)?;
+        board.play(3, Player::Red)?;
+
+        let rows: Vec<_> = RowIterator::wrapping(board, 3).collect();
+        assert_eq!(
+            rows,
+            vec![vec![
+                &Some(Player::Yellow),
+                &None,
+                &None,
+                &Some(Player::Red),
+                &Some(Player::Yellow),
+                &None,
+            ]]
+        );
+        assert_eq!(
+            RowIterator::wrapping(board, 4).next().map(|r| r.len()),
+            Some(4)
+        );
+        assert_eq!(RowIterator::new(board).next().map(|r| r.len()), Some(4));
+        let flat = board::Board::new(1, 4);
+        assert_eq!(
+            RowIterator::wrapping(&flat, 3).next().map(|r| r.len()),
+            Some(4)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn diagonals_wrap_around_cylinders() -> Result<(), board::PlayErr> {
+        let board = &mut board::Board::cylinder(2, 3);
+        board.play(0, Player::Yellow)?;
+        board.play(2, Player::Red)?;
+
+        let main: Vec<_> = MainDiagonalIterator::new(board).collect();
+        assert_eq!(
+            main,
+            vec![
+                vec![&None, &None],
+                vec![&None, &Some(Player::Red)],
+                vec![&None, &Some(Player::Yellow)],
+            ]
+        );
+        let secondary: Vec<_> = SecondaryDiagonalIterator::new(board).collect();
+        assert_eq!(
+            secondary,
+            vec![
+                vec![&None, &Some(Player::Red)],
+                vec![&None, &Some(Player::Yellow)],
+                vec![&None, &None],
+            ]
+        );
+        Ok(())
+    }
 }
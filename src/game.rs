@@ -1,67 +1,824 @@
+use std::sync::Arc;
+
 use super::board;
 use super::validator;
-use crate::board::PlayErr;
+use super::validator::WinInfo;
+use crate::board::{Move, PlayErr};
 use crate::game::GameState::Playing;
 
 #[derive(Clone)]
 pub struct Game {
     board: board::Board,
+    history: Vec<MoveRecord>,
+    connect: usize,
+    rules: Arc<dyn Rules>,
+    rule_state: RuleState,
     pub state: GameState,
     pub current_player: board::Player,
 }
 
+/// Rules of a variant of the game: the moves players can make, what they do
+/// to the board, when the game ends and who plays next. [`Classic`] is the
+/// standard game.
+///
+/// Anything the rules keep track of besides the board, such as the pieces
+/// each player collected in [`PopTen`], goes in a [`RuleState`] that the
+/// game stores and hands back to them. A move may make several moves of the
+/// board, as when a popped piece is put back on top, and taking it back
+/// undoes all of them and restores the state.
+pub trait Rules: Send + Sync {
+    /// Short name of the rules, such as `"classic"`.
+    fn name(&self) -> &str;
+
+    /// State of the rules when a game starts, empty unless they keep one.
+    fn initial_state(&self) -> RuleState {
+        RuleState::default()
+    }
+
+    /// Moves `player` can make on `board`.
+    fn valid_moves(
+        &self,
+        board: &board::Board,
+        state: &RuleState,
+        player: board::Player,
+    ) -> Vec<Move>;
+
+    /// Makes `m` for `player` on `board`, updating `state`, failing when the
+    /// rules don't allow it. `connect` is the pieces in a row needed to win.
+    /// Failed moves must leave the board and the state as they were.
+    fn play(
+        &self,
+        board: &mut board::Board,
+        state: &mut RuleState,
+        connect: usize,
+        m: Move,
+        player: board::Player,
+    ) -> Result<(), PlayErr>;
+
+    /// State of the game once `player` made `m` on `board`, `connect` being
+    /// the pieces in a row needed to win.
+    fn state_after(
+        &self,
+        board: &board::Board,
+        state: &RuleState,
+        connect: usize,
+        m: Move,
+        player: board::Player,
+    ) -> GameState;
+
+    /// Player to move once `player` made `m`. Players take turns unless the
+    /// rules say otherwise.
+    fn next_player(
+        &self,
+        _board: &board::Board,
+        _state: &RuleState,
+        _m: Move,
+        player: board::Player,
+    ) -> board::Player {
+        player.opponent()
+    }
+
+    /// Most moves left before a game on `board` is over, or `None` when
+    /// games can go on forever.
+    fn max_moves_left(&self, _board: &board::Board) -> Option<usize> {
+        None
+    }
+
+    /// Whether rows wrap around, so games are played on a cylinder where
+    /// lines can cross the left and right sides.
+    fn wraps_around(&self) -> bool {
+        false
+    }
+
+    /// Whether these are the rules of [`Classic`], the only ones the
+    /// [`solver`](crate::solver) knows how to play. Leave it `false` for
+    /// any other rules, whatever their name.
+    fn is_classic(&self) -> bool {
+        false
+    }
+}
+
+/// Counters the rules keep for a game besides its board, all starting at
+/// zero. What each one means is up to the rules.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Default)]
+pub struct RuleState {
+    counters: Vec<usize>,
+}
+
+impl RuleState {
+    pub fn get_counter(&self, i: usize) -> usize {
+        self.counters.get(i).copied().unwrap_or(0)
+    }
+
+    pub fn set_counter(&mut self, i: usize, value: usize) {
+        if self.counters.len() <= i {
+            self.counters.resize(i + 1, 0);
+        }
+        self.counters[i] = value;
+    }
+
+    /// Hash of the counters, zero while they are all zero, so that rules
+    /// without a state don't change the hash of a position.
+    pub fn get_hash(&self) -> u64 {
+        let last = self.counters.iter().rposition(|&c| c != 0);
+        let counters = &self.counters[..last.map_or(0, |i| i + 1)];
+        counters.iter().fold(0, |hash, &c| {
+            (hash ^ c as u64 ^ 0x9E37_79B9_7F4A_7C15).wrapping_mul(0x0100_0000_01B3)
+        })
+    }
+}
+
+/// Players drop pieces in turn until one lines up enough of them or the
+/// board is full.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct Classic;
+
+impl Rules for Classic {
+    fn name(&self) -> &str {
+        "classic"
+    }
+
+    fn is_classic(&self) -> bool {
+        true
+    }
+
+    fn valid_moves(
+        &self,
+        board: &board::Board,
+        _state: &RuleState,
+        _player: board::Player,
+    ) -> Vec<Move> {
+        board
+            .get_valid_moves()
+            .into_iter()
+            .map(Move::Drop)
+            .collect()
+    }
+
+    fn play(
+        &self,
+        board: &mut board::Board,
+        _state: &mut RuleState,
+        _connect: usize,
+        m: Move,
+        player: board::Player,
+    ) -> Result<(), PlayErr> {
+        match m {
+            Move::Drop(col) => board.play(col, player),
+            Move::Pop(_) => Err(PlayErr::CannotPop),
+        }
+    }
+
+    fn state_after(
+        &self,
+        board: &board::Board,
+        _state: &RuleState,
+        connect: usize,
+        m: Move,
+        _player: board::Player,
+    ) -> GameState {
+        if let Some(info) = winner_at_top(board, m.get_column(), connect) {
+            GameState::Winner(info)
+        } else if board.is_board_full() {
+            GameState::Tie
+        } else {
+            Playing
+        }
+    }
+
+    fn max_moves_left(&self, board: &board::Board) -> Option<usize> {
+        Some(board.get_rows() * board.get_columns() - board.get_moves())
+    }
+}
+
+/// Players may also remove one of their own pieces from the bottom of a
+/// column instead of dropping one. A full board is only a tie when the
+/// player to move has nothing to pop.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct PopOut;
+
+impl Rules for PopOut {
+    fn name(&self) -> &str {
+        "pop out"
+    }
+
+    fn valid_moves(
+        &self,
+        board: &board::Board,
+        state: &RuleState,
+        player: board::Player,
+    ) -> Vec<Move> {
+        let mut moves = Classic.valid_moves(board, state, player);
+        moves.extend(board.get_valid_pops(player).into_iter().map(Move::Pop));
+        moves
+    }
+
+    fn play(
+        &self,
+        board: &mut board::Board,
+        _state: &mut RuleState,
+        _connect: usize,
+        m: Move,
+        player: board::Player,
+    ) -> Result<(), PlayErr> {
+        board.play_move(m, player)
+    }
+
+    fn state_after(
+        &self,
+        board: &board::Board,
+        _state: &RuleState,
+        connect: usize,
+        m: Move,
+        player: board::Player,
+    ) -> GameState {
+        let winner = match m {
+            Move::Drop(col) => winner_at_top(board, col, connect),
+            Move::Pop(col) => validator::get_winner_in_column(board, col, connect, player),
+        };
+        if let Some(info) = winner {
+            GameState::Winner(info)
+        } else if board.is_board_full() && board.get_valid_pops(player.opponent()).is_empty() {
+            GameState::Tie
+        } else {
+            Playing
+        }
+    }
+}
+
+/// Pop 10: players first fill the board, lines counting for nothing. Then
+/// they take turns popping one of their own pieces from the bottom. A piece
+/// that was part of a line of their pieces is collected, any other goes
+/// back on top of its column. The first player to collect `target` pieces
+/// wins. Players with nothing to pop pass, and the game is a tie when
+/// neither can pop.
 #[derive(Debug, PartialEq, Clone, Copy)]
+pub struct PopTen {
+    pub target: usize,
+}
+
+impl PopTen {
+    /// Counter set once the board was filled and players pop.
+    const POPPING: usize = 2;
+
+    /// Pieces `player` collected in a game in `state`.
+    pub fn get_collected(state: &RuleState, player: board::Player) -> usize {
+        state.get_counter(player.index())
+    }
+
+    fn is_popping(state: &RuleState) -> bool {
+        state.get_counter(PopTen::POPPING) != 0
+    }
+}
+
+impl Default for PopTen {
+    fn default() -> Self {
+        PopTen { target: 10 }
+    }
+}
+
+impl Rules for PopTen {
+    fn name(&self) -> &str {
+        "pop ten"
+    }
+
+    fn valid_moves(
+        &self,
+        board: &board::Board,
+        state: &RuleState,
+        player: board::Player,
+    ) -> Vec<Move> {
+        if PopTen::is_popping(state) {
+            board
+                .get_valid_pops(player)
+                .into_iter()
+                .map(Move::Pop)
+                .collect()
+        } else {
+            Classic.valid_moves(board, state, player)
+        }
+    }
+
+    fn play(
+        &self,
+        board: &mut board::Board,
+        state: &mut RuleState,
+        connect: usize,
+        m: Move,
+        player: board::Player,
+    ) -> Result<(), PlayErr> {
+        match (m, PopTen::is_popping(state)) {
+            (Move::Drop(col), false) => {
+                board.play(col, player)?;
+                if board.is_board_full() {
+                    state.set_counter(PopTen::POPPING, 1);
+                }
+                Ok(())
+            }
+            (Move::Pop(col), true) => {
+                let bottom = board.get_rows() - 1;
+                let in_line = col < board.get_columns()
+                    && validator::get_winner_at(board, bottom, col, connect).is_some();
+                board.pop(col, player)?;
+                if in_line {
+                    let collected = PopTen::get_collected(state, player) + 1;
+                    state.set_counter(player.index(), collected);
+                    Ok(())
+                } else {
+                    board.play(col, player)
+                }
+            }
+            (Move::Drop(_), true) => Err(PlayErr::FullColumn),
+            (Move::Pop(_), false) => Err(PlayErr::CannotPop),
+        }
+    }
+
+    fn state_after(
+        &self,
+        board: &board::Board,
+        state: &RuleState,
+        _connect: usize,
+        _m: Move,
+        player: board::Player,
+    ) -> GameState {
+        if PopTen::get_collected(state, player) >= self.target {
+            GameState::Winner(WinInfo {
+                player,
+                lines: Vec::new(),
+            })
+        } else if PopTen::is_popping(state)
+            && board.get_valid_pops(player).is_empty()
+            && board.get_valid_pops(player.opponent()).is_empty()
+        {
+            GameState::Tie
+        } else {
+            Playing
+        }
+    }
+
+    fn next_player(
+        &self,
+        board: &board::Board,
+        state: &RuleState,
+        _m: Move,
+        player: board::Player,
+    ) -> board::Player {
+        let opponent = player.opponent();
+        if PopTen::is_popping(state) && board.get_valid_pops(opponent).is_empty() {
+            player
+        } else {
+            opponent
+        }
+    }
+}
+
+/// The rules of `R` played on a cylinder, where rows wrap around.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Cylinder<R> {
+    rules: R,
+    name: String,
+}
+
+impl<R: Rules> Cylinder<R> {
+    /// Named after `rules`, as in `"cylinder pop out"`.
+    pub fn new(rules: R) -> Self {
+        let name = format!("cylinder {}", rules.name());
+        Cylinder { rules, name }
+    }
+
+    pub fn get_rules(&self) -> &R {
+        &self.rules
+    }
+}
+
+impl<R: Rules> Rules for Cylinder<R> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn initial_state(&self) -> RuleState {
+        self.rules.initial_state()
+    }
+
+    fn valid_moves(
+        &self,
+        board: &board::Board,
+        state: &RuleState,
+        player: board::Player,
+    ) -> Vec<Move> {
+        self.rules.valid_moves(board, state, player)
+    }
+
+    fn play(
+        &self,
+        board: &mut board::Board,
+        state: &mut RuleState,
+        connect: usize,
+        m: Move,
+        player: board::Player,
+    ) -> Result<(), PlayErr> {
+        self.rules.play(board, state, connect, m, player)
+    }
+
+    fn state_after(
+        &self,
+        board: &board::Board,
+        state: &RuleState,
+        connect: usize,
+        m: Move,
+        player: board::Player,
+    ) -> GameState {
+        self.rules.state_after(board, state, connect, m, player)
+    }
+
+    fn next_player(
+        &self,
+        board: &board::Board,
+        state: &RuleState,
+        m: Move,
+        player: board::Player,
+    ) -> board::Player {
+        self.rules.next_player(board, state, m, player)
+    }
+
+    fn max_moves_left(&self, board: &board::Board) -> Option<usize> {
+        self.rules.max_moves_left(board)
+    }
+
+    fn wraps_around(&self) -> bool {
+        true
+    }
+}
+
+/// Lines through the top piece of `col`, where the last piece was dropped.
+fn winner_at_top(board: &board::Board, col: usize, connect: usize) -> Option<WinInfo> {
+    let row = board.get_rows() - board.get_height(col);
+    validator::get_winner_at(board, row, col, connect)
+}
+
+#[derive(Debug, PartialEq, Clone)]
 pub enum GameState {
     Playing,
     Tie,
-    Winner(board::Player),
+    Winner(WinInfo),
+}
+
+impl GameState {
+    pub fn get_winner(&self) -> Option<board::Player> {
+        match self {
+            GameState::Winner(info) => Some(info.player),
+            _ => None,
+        }
+    }
+}
+
+/// A move as recorded in the game history, along with the state the game
+/// was left in after it.
+#[derive(Debug, PartialEq, Clone)]
+pub struct MoveRecord {
+    pub mv: Move,
+    pub player: board::Player,
+    pub state: GameState,
+    /// Moves the board had before this one, which may have made several.
+    board_plays: usize,
+    /// State of the rules before this move.
+    rule_state: RuleState,
+}
+
+impl MoveRecord {
+    /// Column the move was made in, see `mv` to tell drops from pops.
+    pub fn column(&self) -> usize {
+        self.mv.get_column()
+    }
 }
 
 const ROWS: usize = 6;
 const COLS: usize = 7;
+const CONNECT: usize = 4;
 
 impl Game {
     pub fn new(first_player: board::Player) -> Self {
+        Game::with_size(ROWS, COLS, first_player)
+    }
+
+    pub fn with_size(rows: usize, cols: usize, first_player: board::Player) -> Self {
+        Game::with_connect(rows, cols, CONNECT, first_player)
+    }
+
+    /// Builds a game won by the first player to line up `connect` pieces.
+    ///
+    /// # Panics
+    ///
+    /// When `connect` is zero.
+    pub fn with_connect(
+        rows: usize,
+        cols: usize,
+        connect: usize,
+        first_player: board::Player,
+    ) -> Self {
+        Game::with_rules(rows, cols, connect, Arc::new(Classic), first_player)
+    }
+
+    /// Builds a game played by `rules` instead of the classic ones, on a
+    /// cylinder if the rules wrap around.
+    ///
+    /// # Panics
+    ///
+    /// When `connect` is zero.
+    pub fn with_rules(
+        rows: usize,
+        cols: usize,
+        connect: usize,
+        rules: Arc<dyn Rules>,
+        first_player: board::Player,
+    ) -> Self {
+        assert!(connect > 0, "games need at least one piece in a row to win");
+        let board = if rules.wraps_around() {
+            board::Board::cylinder(rows, cols)
+        } else {
+            board::Board::new(rows, cols)
+        };
         Game {
-            board: board::Board::new(ROWS, COLS),
+            board,
+            history: Vec::new(),
+            connect,
+            rule_state: rules.initial_state(),
+            rules,
             current_player: first_player,
             state: Playing,
         }
     }
 
-    pub fn with_size(rows: usize, cols: usize, first_player: board::Player) -> Self {
+    /// Builds a game from an arbitrary position, with no history to undo.
+    pub(crate) fn from_board(
+        board: board::Board,
+        current_player: board::Player,
+        connect: usize,
+    ) -> Self {
         Game {
-            board: board::Board::new(rows, cols),
-            current_player: first_player,
-            state: Playing,
+            state: evaluate_state(&board, connect),
+            board,
+            history: Vec::new(),
+            connect,
+            rules: Arc::new(Classic),
+            rule_state: RuleState::default(),
+            current_player,
+        }
+    }
+
+    /// Builds a game by playing `moves` in order, failing on the first
+    /// invalid one.
+    pub fn from_moves(
+        rows: usize,
+        cols: usize,
+        first_player: board::Player,
+        moves: &[usize],
+    ) -> Result<Self, PlayErr> {
+        let mut game = Game::with_size(rows, cols, first_player);
+        for &col in moves {
+            game.play(col)?;
         }
+        Ok(game)
     }
 
+    /// Drops a piece of the current player in `col`, see
+    /// [`Game::play_move`].
     pub fn play(&mut self, col: usize) -> Result<(), PlayErr> {
+        self.play_move(Move::Drop(col))
+    }
+
+    /// Plays `m` for the current player. Fails with [`PlayErr::GameOver`]
+    /// once the game has a winner or is tied, leaving it untouched.
+    pub fn play_move(&mut self, m: Move) -> Result<(), PlayErr> {
         if self.state != Playing {
-            return Ok(());
+            return Err(PlayErr::GameOver);
         }
-        self.board.play(col, self.current_player)?;
+        let player = self.current_player;
+        let board_plays = self.board.get_plays();
+        let mut rule_state = self.rule_state.clone();
+        self.rules
+            .play(&mut self.board, &mut rule_state, self.connect, m, player)?;
+        let before = std::mem::replace(&mut self.rule_state, rule_state);
+        let (board, state) = (&self.board, &self.rule_state);
+        self.state = self
+            .rules
+            .state_after(board, state, self.connect, m, player);
+        self.current_player = self.rules.next_player(board, state, m, player);
+        self.history.push(MoveRecord {
+            mv: m,
+            player,
+            state: self.state.clone(),
+            board_plays,
+            rule_state: before,
+        });
+
+        Ok(())
+    }
 
-        if let Some(player) = validator::get_winner(&self.board) {
-            self.state = GameState::Winner(player);
+    /// Moves the current player can make.
+    pub fn get_valid_moves(&self) -> Vec<Move> {
+        self.rules
+            .valid_moves(&self.board, &self.rule_state, self.current_player)
+    }
+
+    /// Builds a game from a move sequence in the notation used by most
+    /// solvers and opening databases, where each character is the 1-based
+    /// column of a move, as in `"4453"`. Columns past 9 continue with `a`
+    /// for the 10th, in lowercase only. Characters that are not a column of
+    /// the board, uppercase letters included, are rejected as out of bounds.
+    pub fn from_notation(
+        rows: usize,
+        cols: usize,
+        first_player: board::Player,
+        notation: &str,
+    ) -> Result<Self, PlayErr> {
+        let mut game = Game::with_size(rows, cols, first_player);
+        game.play_notation(notation)?;
+        Ok(game)
+    }
+
+    /// Plays the moves of `notation`, in the format read by
+    /// [`Game::from_notation`] with pops written as `-` followed by their
+    /// column, as in `"12-1"`. Fails on the first invalid move, leaving the
+    /// moves before it played.
+    pub fn play_notation(&mut self, notation: &str) -> Result<(), PlayErr> {
+        let mut pop = false;
+        for c in notation.trim().chars() {
+            if c == '-' && !pop {
+                pop = true;
+                continue;
+            }
+            let col = match c.to_digit(36) {
+                Some(d) if d > 0 && !c.is_ascii_uppercase() => d as usize - 1,
+                _ => return Err(PlayErr::OutOfBounds),
+            };
+            self.play_move(if pop { Move::Pop(col) } else { Move::Drop(col) })?;
+            pop = false;
         }
+        if pop {
+            return Err(PlayErr::OutOfBounds);
+        }
+        Ok(())
+    }
 
-        if self.board.is_board_full() {
-            self.state = GameState::Tie;
+    /// Writes the moves played so far in the notation read by
+    /// [`Game::play_notation`].
+    pub fn to_notation(&self) -> String {
+        let mut notation = String::new();
+        for record in &self.history {
+            if let Move::Pop(_) = record.mv {
+                notation.push('-');
+            }
+            let col = record.mv.get_column() as u32 + 1;
+            notation.push(std::char::from_digit(col, 36).unwrap_or('?'));
         }
+        notation
+    }
 
-        self.current_player = match self.current_player {
-            board::Player::Yellow => board::Player::Red,
-            board::Player::Red => board::Player::Yellow,
-        };
+    /// Takes back the last move, restoring the board, the state and the
+    /// player to move. Returns the column of the move taken back, see
+    /// [`Game::undo_play`] to tell drops from pops.
+    pub fn undo(&mut self) -> Option<usize> {
+        self.undo_play().map(|m| m.get_column())
+    }
 
-        Ok(())
+    /// Same as [`Game::undo`], returning the move taken back.
+    pub fn undo_play(&mut self) -> Option<Move> {
+        let record = self.history.pop()?;
+        while self.board.get_plays() > record.board_plays {
+            self.board.undo_play();
+        }
+        self.rule_state = record.rule_state;
+        self.state = Playing;
+        self.current_player = record.player;
+        Some(record.mv)
+    }
+
+    pub fn history(&self) -> &[MoveRecord] {
+        &self.history
+    }
+
+    /// Iterates over every position of the game, from the starting one up to
+    /// the current one.
+    pub fn replay(&self) -> Replay<'_> {
+        let mut start = self.clone();
+        while start.undo().is_some() {}
+        Replay {
+            game: start,
+            history: &self.history,
+            pos: None,
+        }
     }
 
     pub fn get_board(&self) -> &board::Board {
         &self.board
     }
+
+    /// Number of pieces in a row needed to win.
+    pub fn get_connect(&self) -> usize {
+        self.connect
+    }
+
+    pub fn get_rules(&self) -> &dyn Rules {
+        self.rules.as_ref()
+    }
+
+    /// What the rules keep track of besides the board.
+    pub fn get_rule_state(&self) -> &RuleState {
+        &self.rule_state
+    }
+}
+
+pub(crate) fn evaluate_state(board: &board::Board, connect: usize) -> GameState {
+    if let Some(info) = validator::get_winner(board, connect) {
+        GameState::Winner(info)
+    } else if board.is_board_full() {
+        GameState::Tie
+    } else {
+        Playing
+    }
+}
+
+pub struct Replay<'a> {
+    game: Game,
+    history: &'a [MoveRecord],
+    pos: Option<usize>,
+}
+
+impl<'a> Iterator for Replay<'a> {
+    type Item = Game;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let pos = match self.pos {
+            None => {
+                self.pos = Some(0);
+                return Some(self.game.clone());
+            }
+            Some(p) => p,
+        };
+        let record = self.history.get(pos)?;
+        self.game
+            .play_move(record.mv)
+            .expect("recorded moves should be valid");
+        self.pos = Some(pos + 1);
+        Some(self.game.clone())
+    }
+}
+
+/// Classic rules, except that dropping a piece in the first column
+/// earns another move. Used by tests of rules other than the built-in ones.
+#[cfg(test)]
+pub(crate) struct FirstColumnBonus;
+
+#[cfg(test)]
+impl Rules for FirstColumnBonus {
+    fn name(&self) -> &str {
+        "first column bonus"
+    }
+
+    fn valid_moves(
+        &self,
+        board: &board::Board,
+        state: &RuleState,
+        player: board::Player,
+    ) -> Vec<Move> {
+        Classic.valid_moves(board, state, player)
+    }
+
+    fn play(
+        &self,
+        board: &mut board::Board,
+        state: &mut RuleState,
+        connect: usize,
+        m: Move,
+        player: board::Player,
+    ) -> Result<(), PlayErr> {
+        Classic.play(board, state, connect, m, player)
+    }
+
+    fn state_after(
+        &self,
+        board: &board::Board,
+        state: &RuleState,
+        connect: usize,
+        m: Move,
+        player: board::Player,
+    ) -> GameState {
+        Classic.state_after(board, state, connect, m, player)
+    }
+
+    fn next_player(
+        &self,
+        _board: &board::Board,
+        _state: &RuleState,
+        m: Move,
+        player: board::Player,
+    ) -> board::Player {
+        match m {
+            Move::Drop(0) => player,
+            _ => player.opponent(),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -95,7 +852,7 @@ mod test {
             for _ in 0..game.board.get_rows() {
                 assert_eq!(game_state, GameState::Playing);
                 game.play(c)?;
-                game_state = game.state;
+                game_state = game.state.clone();
             }
         }
 
@@ -112,7 +869,7 @@ mod test {
         game.play(0)?;
         let r = game.play(0);
         let current_player = game.current_player;
-        let current_winner = game.state;
+        let current_winner = game.state.clone();
         let current_board = game.board.clone();
         assert_eq!(Err(board::PlayErr::FullColumn), r);
 
@@ -122,4 +879,401 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn undo_restores_winning_move() -> Result<(), board::PlayErr> {
+        let mut game = Game::new(board::Player::Yellow);
+        for &c in &[0, 1, 0, 1, 0, 1] {
+            game.play(c)?;
+        }
+        let before_win = game.clone();
+        game.play(0)?;
+        assert_eq!(game.state.get_winner(), Some(board::Player::Yellow));
+
+        assert_eq!(game.undo(), Some(0));
+        assert_eq!(game.state, before_win.state);
+        assert_eq!(game.current_player, before_win.current_player);
+        assert_eq!(game.board, before_win.board);
+
+        Ok(())
+    }
+
+    #[test]
+    fn history_records_every_move() -> Result<(), board::PlayErr> {
+        let game = Game::from_moves(6, 7, board::Player::Red, &[3, 3, 4])?;
+        let history = game.history();
+
+        assert_eq!(history.len(), 3);
+        assert_eq!(history[0].mv, Move::Drop(3));
+        assert_eq!(history[0].player, board::Player::Red);
+        assert_eq!(history[1].player, board::Player::Yellow);
+        assert_eq!(history[2].mv, Move::Drop(4));
+        assert_eq!(history[2].state, GameState::Playing);
+
+        Ok(())
+    }
+
+    #[test]
+    fn history_records_final_state() -> Result<(), board::PlayErr> {
+        let mut game = Game::from_moves(6, 7, board::Player::Yellow, &[0, 1, 0, 1, 0, 1, 0])?;
+        let last = game.history().last().cloned();
+        assert_eq!(
+            last.and_then(|r| r.state.get_winner()),
+            Some(board::Player::Yellow)
+        );
+
+        game.undo();
+        assert_eq!(game.history().len(), 6);
+        Ok(())
+    }
+
+    #[test]
+    fn from_moves_rejects_invalid_moves() {
+        let r = Game::from_moves(3, 3, board::Player::Yellow, &[0, 0, 0, 0]);
+        assert_eq!(r.err(), Some(board::PlayErr::FullColumn));
+
+        let r = Game::from_moves(3, 3, board::Player::Yellow, &[3]);
+        assert_eq!(r.err(), Some(board::PlayErr::OutOfBounds));
+    }
+
+    #[test]
+    fn notation_round_trip() -> Result<(), board::PlayErr> {
+        let game = Game::from_notation(6, 7, board::Player::Yellow, "4453")?;
+        assert_eq!(game.history().len(), 4);
+        assert_eq!(game.history()[2].mv, Move::Drop(4));
+        assert_eq!(game.to_notation(), "4453");
+
+        Ok(())
+    }
+
+    #[test]
+    fn pop_out_notation_round_trip() -> Result<(), board::PlayErr> {
+        let mut game = Game::with_rules(6, 7, 4, Arc::new(PopOut), board::Player::Yellow);
+        game.play_notation("1232-13-3")?;
+        assert_eq!(game.history()[4].mv, Move::Pop(0));
+        assert_eq!(game.history()[6].mv, Move::Pop(2));
+        assert_eq!(game.to_notation(), "1232-13-3");
+
+        let mut copy = Game::with_rules(6, 7, 4, Arc::new(PopOut), board::Player::Yellow);
+        copy.play_notation(&game.to_notation())?;
+        assert_eq!(copy.get_board(), game.get_board());
+        assert_eq!(copy.history(), game.history());
+
+        let mut classic = Game::new(board::Player::Yellow);
+        assert_eq!(classic.play_notation("1-1"), Err(board::PlayErr::CannotPop));
+        let mut pop_out = Game::with_rules(6, 7, 4, Arc::new(PopOut), board::Player::Yellow);
+        assert_eq!(
+            pop_out.play_notation("1-"),
+            Err(board::PlayErr::OutOfBounds)
+        );
+        assert_eq!(
+            pop_out.play_notation("--1"),
+            Err(board::PlayErr::OutOfBounds)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn notation_respects_board_size() -> Result<(), board::PlayErr> {
+        let game = Game::from_notation(3, 12, board::Player::Red, "1ac")?;
+        assert_eq!(game.get_board().get_last_move(), Some(11));
+        assert_eq!(game.to_notation(), "1ac");
+
+        let r = Game::from_notation(6, 7, board::Player::Yellow, "48");
+        assert_eq!(r.err(), Some(board::PlayErr::OutOfBounds));
+        let r = Game::from_notation(3, 12, board::Player::Yellow, "1AC");
+        assert_eq!(r.err(), Some(board::PlayErr::OutOfBounds));
+        let r = Game::from_notation(6, 7, board::Player::Yellow, "40");
+        assert_eq!(r.err(), Some(board::PlayErr::OutOfBounds));
+        let r = Game::from_notation(6, 7, board::Player::Yellow, "4-4");
+        assert_eq!(r.err(), Some(board::PlayErr::CannotPop));
+        let r = Game::from_notation(3, 3, board::Player::Yellow, "1111");
+        assert_eq!(r.err(), Some(board::PlayErr::FullColumn));
+
+        Ok(())
+    }
+
+    #[test]
+    fn replay_yields_every_position() -> Result<(), board::PlayErr> {
+        let moves = [3, 2, 3, 2];
+        let game = Game::from_moves(6, 7, board::Player::Yellow, &moves)?;
+        let positions: Vec<Game> = game.replay().collect();
+
+        assert_eq!(positions.len(), moves.len() + 1);
+        assert_eq!(positions[0].board, board::Board::new(6, 7));
+        assert_eq!(positions[0].current_player, board::Player::Yellow);
+        for (i, position) in positions.iter().enumerate() {
+            assert_eq!(position.history(), &game.history()[..i]);
+        }
+        assert_eq!(positions[4].board, game.board);
+
+        Ok(())
+    }
+
+    #[test]
+    fn undo_all_moves() -> Result<(), board::PlayErr> {
+        let mut game = Game::with_size(3, 3, board::Player::Red);
+        for c in 0..3 {
+            for _ in 0..3 {
+                game.play(c)?;
+            }
+        }
+        assert_eq!(game.state, GameState::Tie);
+
+        for _ in 0..9 {
+            assert!(game.undo().is_some());
+        }
+        assert_eq!(game.undo(), None);
+        assert_eq!(game.state, GameState::Playing);
+        assert_eq!(game.current_player, board::Player::Red);
+        assert_eq!(game.board, board::Board::new(3, 3));
+
+        Ok(())
+    }
+
+    #[test]
+    fn connect_length_decides_the_winner() -> Result<(), board::PlayErr> {
+        let mut three = Game::with_connect(6, 7, 3, board::Player::Yellow);
+        let mut five = Game::with_connect(6, 7, 5, board::Player::Yellow);
+        for &col in &[0, 0, 1, 1, 2] {
+            three.play(col)?;
+            five.play(col)?;
+        }
+        assert_eq!(three.state.get_winner(), Some(board::Player::Yellow));
+        assert_eq!(five.state, GameState::Playing);
+
+        for &col in &[2, 3, 3, 4] {
+            five.play(col)?;
+        }
+        assert_eq!(five.state.get_winner(), Some(board::Player::Yellow));
+        assert_eq!(five.get_connect(), 5);
+        Ok(())
+    }
+
+    #[test]
+    fn winner_state_holds_the_winning_cells() -> Result<(), board::PlayErr> {
+        let game = Game::from_moves(6, 7, board::Player::Yellow, &[0, 6, 1, 6, 2, 6, 3])?;
+        let info = match game.state {
+            GameState::Winner(info) => info,
+            state => panic!("expected a winner, got {:?}", state),
+        };
+
+        assert_eq!(info.player, board::Player::Yellow);
+        assert_eq!(info.lines.len(), 1);
+        assert_eq!(info.lines[0].direction, validator::Direction::Horizontal);
+        assert_eq!(info.get_cells(), vec![(5, 0), (5, 1), (5, 2), (5, 3)]);
+        Ok(())
+    }
+
+    #[test]
+    fn play_after_the_game_ended_fails() -> Result<(), board::PlayErr> {
+        let mut game = Game::from_moves(6, 7, board::Player::Yellow, &[0, 1, 0, 1, 0, 1, 0])?;
+        let before = game.clone();
+
+        assert_eq!(game.play(2), Err(board::PlayErr::GameOver));
+        assert_eq!(game.board, before.board);
+        assert_eq!(game.history(), before.history());
+        assert_eq!(game.current_player, before.current_player);
+        Ok(())
+    }
+
+    #[test]
+    fn win_on_the_last_cell_is_not_a_tie() -> Result<(), board::PlayErr> {
+        let mut game = Game::with_connect(3, 3, 3, board::Player::Yellow);
+        for &col in &[0, 0, 0, 1, 1, 2, 1, 2, 2] {
+            game.play(col)?;
+        }
+
+        assert!(game.board.is_board_full());
+        assert_eq!(game.state.get_winner(), Some(board::Player::Yellow));
+        assert_eq!(evaluate_state(&game.board, 3), game.state);
+        Ok(())
+    }
+
+    #[test]
+    fn pop_out_allows_popping_own_pieces() -> Result<(), board::PlayErr> {
+        let mut classic = Game::from_moves(6, 7, board::Player::Yellow, &[0, 1])?;
+        assert_eq!(
+            classic.play_move(Move::Pop(0)),
+            Err(board::PlayErr::CannotPop)
+        );
+        assert_eq!(classic.get_valid_moves().len(), 7);
+
+        let mut game = Game::with_rules(6, 7, 4, Arc::new(PopOut), board::Player::Yellow);
+        game.play(0)?;
+        game.play(1)?;
+        assert_eq!(game.get_valid_moves().len(), 8);
+        assert!(game.get_valid_moves().contains(&Move::Pop(0)));
+        assert_eq!(game.play_move(Move::Pop(1)), Err(board::PlayErr::CannotPop));
+        game.play_move(Move::Pop(0))?;
+
+        assert_eq!(game.get_board().get_height(0), 0);
+        assert_eq!(game.current_player, board::Player::Red);
+        assert_eq!(game.to_notation(), "12-1");
+        assert_eq!(
+            game.replay().last().map(|g| g.to_notation()),
+            Some(game.to_notation())
+        );
+        assert_eq!(game.history()[2].column(), 0);
+        assert_eq!(game.undo_play(), Some(Move::Pop(0)));
+        assert_eq!(game.get_board().get_height(0), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn pop_can_win() -> Result<(), board::PlayErr> {
+        let mut game = Game::with_rules(6, 7, 4, Arc::new(PopOut), board::Player::Yellow);
+        // Yellow has three on the second row and pops column 3, which has
+        // yellow pieces under and over a red one, lining up a fourth.
+        for &col in &[3, 3, 3, 0, 0, 1, 1, 6, 2, 6, 2, 6] {
+            game.play(col)?;
+        }
+        assert_eq!(game.state, GameState::Playing);
+        game.play_move(Move::Pop(3))?;
+        assert_eq!(game.state.get_winner(), Some(board::Player::Yellow));
+        Ok(())
+    }
+
+    #[test]
+    fn full_pop_out_board_is_only_tied_without_pops() -> Result<(), board::PlayErr> {
+        let mut game = Game::with_rules(1, 2, 2, Arc::new(PopOut), board::Player::Yellow);
+        game.play(0)?;
+        game.play(1)?;
+        assert_eq!(game.state, GameState::Playing);
+        assert_eq!(game.get_valid_moves(), vec![Move::Pop(0)]);
+        game.play_move(Move::Pop(0))?;
+        assert_eq!(game.state, GameState::Playing);
+
+        // Red has no piece at the bottom of the only column.
+        let mut tied = Game::with_rules(3, 1, 2, Arc::new(PopOut), board::Player::Yellow);
+        for _ in 0..3 {
+            tied.play(0)?;
+        }
+        assert_eq!(tied.state, GameState::Tie);
+        Ok(())
+    }
+
+    #[test]
+    fn rules_decide_who_plays_next() -> Result<(), board::PlayErr> {
+        let mut game = Game::with_rules(6, 7, 4, Arc::new(FirstColumnBonus), board::Player::Yellow);
+        game.play(0)?;
+        assert_eq!(game.current_player, board::Player::Yellow);
+        game.play(1)?;
+        assert_eq!(game.current_player, board::Player::Red);
+        game.play(0)?;
+        assert_eq!(game.current_player, board::Player::Red);
+
+        assert_eq!(game.undo(), Some(0));
+        assert_eq!(game.current_player, board::Player::Red);
+        assert_eq!(game.undo(), Some(1));
+        assert_eq!(game.current_player, board::Player::Yellow);
+        assert_eq!(game.get_rules().name(), "first column bonus");
+        Ok(())
+    }
+
+    #[test]
+    fn pop_ten_collects_pieces_in_lines() -> Result<(), board::PlayErr> {
+        let rules = Arc::new(PopTen { target: 2 });
+        let mut game = Game::with_rules(2, 2, 2, rules, board::Player::Yellow);
+        assert_eq!(game.play_move(Move::Pop(0)), Err(board::PlayErr::CannotPop));
+        for &col in &[0, 1, 0, 1] {
+            game.play(col)?;
+        }
+        // Lines made while filling the board don't win.
+        assert_eq!(game.state, GameState::Playing);
+        assert_eq!(game.get_valid_moves(), vec![Move::Pop(0)]);
+
+        game.play_move(Move::Pop(0))?;
+        game.play_move(Move::Pop(1))?;
+        let collected = |g: &Game, p| PopTen::get_collected(g.get_rule_state(), p);
+        assert_eq!(collected(&game, board::Player::Yellow), 1);
+        assert_eq!(collected(&game, board::Player::Red), 1);
+        assert_eq!(game.play(0), Err(board::PlayErr::FullColumn));
+
+        // Yellow's last piece is in no line, so it goes back on top.
+        let before = game.clone();
+        game.play_move(Move::Pop(0))?;
+        assert_eq!(
+            game.get_board().get_pieces(),
+            before.get_board().get_pieces()
+        );
+        assert_eq!(collected(&game, board::Player::Yellow), 1);
+        assert_eq!(game.to_notation(), "1212-1-2-1");
+
+        assert_eq!(game.undo_play(), Some(Move::Pop(0)));
+        assert_eq!(game.get_board(), before.get_board());
+        assert_eq!(game.undo_play(), Some(Move::Pop(1)));
+        assert_eq!(collected(&game, board::Player::Red), 0);
+        assert_eq!(game.get_board().get_height(1), 2);
+        assert_eq!(game.current_player, board::Player::Red);
+        Ok(())
+    }
+
+    #[test]
+    fn pop_ten_is_won_by_collecting_the_target() -> Result<(), board::PlayErr> {
+        let rules = Arc::new(PopTen { target: 1 });
+        let mut game = Game::with_rules(2, 2, 2, rules, board::Player::Yellow);
+        game.play_notation("1212-1")?;
+        assert_eq!(game.state.get_winner(), Some(board::Player::Yellow));
+        Ok(())
+    }
+
+    #[test]
+    fn pop_ten_passes_and_ties_without_pops() -> Result<(), board::PlayErr> {
+        let mut game =
+            Game::with_rules(1, 3, 1, Arc::new(PopTen::default()), board::Player::Yellow);
+        game.play_notation("123-2-1")?;
+        // Red has nothing left to pop.
+        assert_eq!(game.current_player, board::Player::Yellow);
+        game.play_move(Move::Pop(2))?;
+        assert_eq!(game.state, GameState::Tie);
+        assert_eq!(
+            PopTen::get_collected(game.get_rule_state(), board::Player::Yellow),
+            2
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn rule_states_only_hash_when_set() {
+        let mut state = RuleState::default();
+        state.set_counter(3, 0);
+        assert_eq!(state.get_hash(), 0);
+        state.set_counter(1, 2);
+        assert_eq!(state.get_counter(1), 2);
+        assert_eq!(state.get_counter(7), 0);
+        assert_ne!(state.get_hash(), 0);
+    }
+
+    #[test]
+    fn classic_rules_are_the_default() {
+        let game = Game::new(board::Player::Yellow);
+        assert_eq!(game.get_rules().name(), Classic.name());
+        assert_eq!(
+            game.get_rules().max_moves_left(game.get_board()),
+            Some(6 * 7)
+        );
+        assert_eq!(PopOut.max_moves_left(game.get_board()), None);
+    }
+
+    #[test]
+    fn cylinder_lines_cross_the_sides() -> Result<(), board::PlayErr> {
+        let moves = [5, 3, 6, 3, 0, 3, 1];
+        let mut flat = Game::with_rules(6, 7, 4, Arc::new(Classic), board::Player::Yellow);
+        let rules = Arc::new(Cylinder::new(Classic));
+        let mut cylinder = Game::with_rules(6, 7, 4, rules, board::Player::Yellow);
+        for &col in &moves {
+            flat.play(col)?;
+            cylinder.play(col)?;
+        }
+        assert_eq!(flat.state, GameState::Playing);
+        assert!(cylinder.get_board().is_cylinder());
+        assert_eq!(cylinder.get_rules().name(), "cylinder classic");
+        assert_eq!(cylinder.state.get_winner(), Some(board::Player::Yellow));
+        assert_eq!(
+            evaluate_state(cylinder.get_board(), 4),
+            cylinder.state.clone()
+        );
+        Ok(())
+    }
 }
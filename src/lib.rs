@@ -1,5 +1,12 @@
+pub mod agent;
 pub mod ai;
+pub mod bitboard;
 pub mod board;
 pub mod board_iterators;
 pub mod game;
+pub mod position;
+mod rng;
+pub mod solver;
+pub mod threats;
+pub mod transposition;
 pub mod validator;
@@ -0,0 +1,517 @@
+//! Compact textual format for positions, loosely modeled on chess FEN.
+//!
+//! A board is written as `<rows>x<cols> <cells>`, where `<cells>` lists the
+//! rows from top to bottom separated by `/`. Each row uses `y` and `r` for
+//! pieces and a number for a run of empty cells, so an empty 6x7 board is
+//! `6x7 7/7/7/7/7/7`. Boards have at most [`MAX_SIZE`] rows and columns.
+//!
+//! A game adds the player to move (`y` or `r`) and the state of the game:
+//! `-` while playing, `t` for a tie, or the winner as `y` or `r`, as in
+//! `6x7 7/7/7/7/7/3y3 r -`. Games won by lines other than four add the
+//! length of the line at the end, as in `6x7 7/7/7/7/7/3y3 r - 5`.
+//! Positions don't record the rules, so games always load as classic games
+//! on a flat board, even when written from a Pop Out game or a cylinder.
+
+use super::board::{Board, Player};
+use super::game::{evaluate_state, Game, GameState};
+use std::error;
+use std::fmt;
+use std::str::FromStr;
+
+/// Most rows or columns a position can have.
+pub const MAX_SIZE: usize = 64;
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum ParsePositionErr {
+    MissingField(&'static str),
+    TrailingInput(String),
+    InvalidSize(String),
+    WrongRowCount {
+        expected: usize,
+        found: usize,
+    },
+    WrongRowLength {
+        row: usize,
+        expected: usize,
+        found: usize,
+    },
+    /// A row holds more cells than the board has columns, counted up to
+    /// the first cell past the end.
+    RowTooLong {
+        row: usize,
+        expected: usize,
+    },
+    InvalidCell {
+        row: usize,
+        found: char,
+    },
+    FloatingPiece {
+        row: usize,
+        col: usize,
+    },
+    UnbalancedPieces {
+        yellow: usize,
+        red: usize,
+    },
+    InvalidPlayer(String),
+    InvalidState(String),
+    InvalidConnect(String),
+    StateMismatch {
+        declared: char,
+        actual: char,
+    },
+}
+
+impl fmt::Display for ParsePositionErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParsePositionErr::MissingField(field) => write!(f, "missing {}", field),
+            ParsePositionErr::TrailingInput(s) => write!(f, "unexpected trailing input `{}`", s),
+            ParsePositionErr::InvalidSize(s) => write!(
+                f,
+                "invalid size `{}`, expected <rows>x<cols> of at most {} each",
+                s, MAX_SIZE
+            ),
+            ParsePositionErr::WrongRowCount { expected, found } => {
+                write!(f, "expected {} rows, found {}", expected, found)
+            }
+            ParsePositionErr::WrongRowLength {
+                row,
+                expected,
+                found,
+            } => write!(
+                f,
+                "row {} has {} cells, expected {}",
+                row + 1,
+                found,
+                expected
+            ),
+            ParsePositionErr::RowTooLong { row, expected } => {
+                write!(f, "row {} has more than {} cells", row + 1, expected)
+            }
+            ParsePositionErr::InvalidCell { row, found } => {
+                write!(f, "invalid cell `{}` in row {}", found, row + 1)
+            }
+            ParsePositionErr::FloatingPiece { row, col } => write!(
+                f,
+                "piece at row {}, column {} has an empty cell below it",
+                row + 1,
+                col + 1
+            ),
+            ParsePositionErr::UnbalancedPieces { yellow, red } => write!(
+                f,
+                "{} yellow and {} red pieces cannot happen in a game",
+                yellow, red
+            ),
+            ParsePositionErr::InvalidPlayer(s) => {
+                write!(f, "invalid player `{}`, expected y or r", s)
+            }
+            ParsePositionErr::InvalidState(s) => {
+                write!(f, "invalid state `{}`, expected -, t, y or r", s)
+            }
+            ParsePositionErr::InvalidConnect(s) => {
+                write!(f, "invalid line length `{}`, expected a positive number", s)
+            }
+            ParsePositionErr::StateMismatch { declared, actual } => write!(
+                f,
+                "declared state {:?} does not match the board, which is {:?}",
+                declared, actual
+            ),
+        }
+    }
+}
+
+impl error::Error for ParsePositionErr {}
+
+impl fmt::Display for Board {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}x{} ", self.get_rows(), self.get_columns())?;
+        for row in 0..self.get_rows() {
+            if row > 0 {
+                write!(f, "/")?;
+            }
+            let mut empty = 0;
+            for col in 0..self.get_columns() {
+                match self.get_piece_at(row, col) {
+                    None => empty += 1,
+                    Some(p) => {
+                        if empty > 0 {
+                            write!(f, "{}", empty)?;
+                            empty = 0;
+                        }
+                        write!(f, "{}", player_char(p))?;
+                    }
+                }
+            }
+            if empty > 0 {
+                write!(f, "{}", empty)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Board {
+    type Err = ParsePositionErr;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut fields = s.split_whitespace();
+        let board = parse_board(&mut fields)?;
+        match fields.next() {
+            Some(rest) => Err(ParsePositionErr::TrailingInput(rest.to_string())),
+            None => Ok(board),
+        }
+    }
+}
+
+impl fmt::Display for Game {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {}",
+            self.get_board(),
+            player_char(self.current_player),
+            state_char(&self.state)
+        )?;
+        if self.get_connect() != 4 {
+            write!(f, " {}", self.get_connect())?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Game {
+    type Err = ParsePositionErr;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut fields = s.split_whitespace();
+        let board = parse_board(&mut fields)?;
+
+        let side = fields
+            .next()
+            .ok_or(ParsePositionErr::MissingField("player to move"))?;
+        let current_player = match side {
+            "y" => Player::Yellow,
+            "r" => Player::Red,
+            _ => return Err(ParsePositionErr::InvalidPlayer(side.to_string())),
+        };
+
+        let state = fields
+            .next()
+            .ok_or(ParsePositionErr::MissingField("game state"))?;
+        let declared = match state {
+            "-" | "t" | "y" | "r" => state.chars().next().expect("state is not empty"),
+            _ => return Err(ParsePositionErr::InvalidState(state.to_string())),
+        };
+        let connect = match fields.next() {
+            None => 4,
+            Some(c) => match c.parse() {
+                Ok(n) if n > 0 => n,
+                _ => return Err(ParsePositionErr::InvalidConnect(c.to_string())),
+            },
+        };
+        if let Some(rest) = fields.next() {
+            return Err(ParsePositionErr::TrailingInput(rest.to_string()));
+        }
+
+        let (yellow, red) = count_pieces(&board);
+        let balanced = match current_player {
+            Player::Yellow => red == yellow || red == yellow + 1,
+            Player::Red => yellow == red || yellow == red + 1,
+        };
+        if !balanced {
+            return Err(ParsePositionErr::UnbalancedPieces { yellow, red });
+        }
+
+        let actual = state_char(&evaluate_state(&board, connect));
+        if actual != declared {
+            return Err(ParsePositionErr::StateMismatch { declared, actual });
+        }
+
+        Ok(Game::from_board(board, current_player, connect))
+    }
+}
+
+fn parse_board<'a, I>(fields: &mut I) -> Result<Board, ParsePositionErr>
+where
+    I: Iterator<Item = &'a str>,
+{
+    let size = fields
+        .next()
+        .ok_or(ParsePositionErr::MissingField("size"))?;
+    let (rows, cols) = parse_size(size)?;
+    let cells = fields
+        .next()
+        .ok_or(ParsePositionErr::MissingField("cells"))?;
+
+    let lines: Vec<&str> = cells.split('/').collect();
+    if lines.len() != rows {
+        return Err(ParsePositionErr::WrongRowCount {
+            expected: rows,
+            found: lines.len(),
+        });
+    }
+
+    let mut pieces = Vec::with_capacity(rows * cols);
+    for (row, line) in lines.into_iter().enumerate() {
+        let row_pieces = parse_row(row, line, cols)?;
+        if row_pieces.len() != cols {
+            return Err(ParsePositionErr::WrongRowLength {
+                row,
+                expected: cols,
+                found: row_pieces.len(),
+            });
+        }
+        pieces.extend(row_pieces);
+    }
+
+    for row in 0..rows - 1 {
+        for col in 0..cols {
+            if pieces[row * cols + col].is_some() && pieces[(row + 1) * cols + col].is_none() {
+                return Err(ParsePositionErr::FloatingPiece { row, col });
+            }
+        }
+    }
+
+    Ok(Board::from_pieces(rows, cols, pieces))
+}
+
+fn parse_size(size: &str) -> Result<(usize, usize), ParsePositionErr> {
+    let invalid = || ParsePositionErr::InvalidSize(size.to_string());
+    let mut dims = size.splitn(2, 'x');
+    let rows: usize = dims
+        .next()
+        .and_then(|r| r.parse().ok())
+        .ok_or_else(invalid)?;
+    let cols: usize = dims
+        .next()
+        .and_then(|c| c.parse().ok())
+        .ok_or_else(invalid)?;
+    if rows == 0 || cols == 0 || rows > MAX_SIZE || cols > MAX_SIZE {
+        return Err(invalid());
+    }
+    Ok((rows, cols))
+}
+
+/// Reads the cells of a row, failing as soon as there are more than `cols`
+/// of them, so huge runs of empty cells are never allocated.
+fn parse_row(row: usize, line: &str, cols: usize) -> Result<Vec<Option<Player>>, ParsePositionErr> {
+    let too_long = ParsePositionErr::RowTooLong {
+        row,
+        expected: cols,
+    };
+    let mut pieces = Vec::with_capacity(cols);
+    let mut empty: usize = 0;
+    for c in line.chars() {
+        if let Some(d) = c.to_digit(10) {
+            empty = empty
+                .checked_mul(10)
+                .and_then(|e| e.checked_add(d as usize))
+                .filter(|&e| pieces.len() + e <= cols)
+                .ok_or_else(|| too_long.clone())?;
+            continue;
+        }
+        if pieces.len() + empty >= cols {
+            return Err(too_long);
+        }
+        pieces.extend((0..empty).map(|_| None));
+        empty = 0;
+        match c {
+            'y' => pieces.push(Some(Player::Yellow)),
+            'r' => pieces.push(Some(Player::Red)),
+            _ => return Err(ParsePositionErr::InvalidCell { row, found: c }),
+        }
+    }
+    pieces.extend((0..empty).map(|_| None));
+    Ok(pieces)
+}
+
+fn count_pieces(board: &Board) -> (usize, usize) {
+    board
+        .get_pieces()
+        .iter()
+        .fold((0, 0), |(yellow, red), p| match p {
+            Some(Player::Yellow) => (yellow + 1, red),
+            Some(Player::Red) => (yellow, red + 1),
+            None => (yellow, red),
+        })
+}
+
+fn state_char(state: &GameState) -> char {
+    match state {
+        GameState::Playing => '-',
+        GameState::Tie => 't',
+        GameState::Winner(info) => player_char(info.player),
+    }
+}
+
+fn player_char(player: Player) -> char {
+    match player {
+        Player::Yellow => 'y',
+        Player::Red => 'r',
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn empty_board_round_trip() {
+        let board = Board::new(6, 7);
+        assert_eq!(board.to_string(), "6x7 7/7/7/7/7/7");
+        assert_eq!("6x7 7/7/7/7/7/7".parse(), Ok(board));
+    }
+
+    #[test]
+    fn game_round_trip() -> Result<(), ParsePositionErr> {
+        let game =
+            Game::from_moves(6, 7, Player::Yellow, &[3, 3, 4, 2, 6]).expect("moves are valid");
+        let position = game.to_string();
+        assert_eq!(position, "6x7 7/7/7/7/3r3/2ryy1y r -");
+
+        let parsed: Game = position.parse()?;
+        assert_eq!(
+            parsed.get_board().get_pieces(),
+            game.get_board().get_pieces()
+        );
+        assert_eq!(parsed.current_player, game.current_player);
+        assert_eq!(parsed.state, game.state);
+        assert_eq!(parsed.to_string(), position);
+
+        Ok(())
+    }
+
+    #[test]
+    fn wide_boards_use_multi_digit_runs() -> Result<(), ParsePositionErr> {
+        let mut board = Board::new(2, 12);
+        board.play(11, Player::Red).expect("column is empty");
+        assert_eq!(board.to_string(), "2x12 12/11r");
+
+        let parsed: Board = "2x12 12/11r".parse()?;
+        assert_eq!(parsed.get_piece_at(1, 11), Some(Player::Red));
+        Ok(())
+    }
+
+    #[test]
+    fn loaded_game_keeps_playing() -> Result<(), ParsePositionErr> {
+        let mut game: Game = "4x4 4/y3/y3/yrr1 r -".parse()?;
+        game.play(3).expect("column is empty");
+        assert_eq!(game.state, GameState::Playing);
+        game.play(0).expect("column has room");
+        assert_eq!(game.state.get_winner(), Some(Player::Yellow));
+        Ok(())
+    }
+
+    #[test]
+    fn connect_length_round_trip() -> Result<(), ParsePositionErr> {
+        let game: Game = "4x4 4/4/4/yyr1 r - 3".parse()?;
+        assert_eq!(game.get_connect(), 3);
+        assert_eq!(game.to_string(), "4x4 4/4/4/yyr1 r - 3");
+
+        let won: Game = "4x4 4/4/r3/yyyr r y 3".parse()?;
+        assert_eq!(won.state.get_winner(), Some(Player::Yellow));
+        Ok(())
+    }
+
+    #[test]
+    fn reports_descriptive_errors() {
+        assert_eq!(
+            "6x7".parse::<Board>(),
+            Err(ParsePositionErr::MissingField("cells"))
+        );
+        assert_eq!(
+            "6by7 7".parse::<Board>(),
+            Err(ParsePositionErr::InvalidSize(String::from("6by7")))
+        );
+        assert_eq!(
+            "2x2 2".parse::<Board>(),
+            Err(ParsePositionErr::WrongRowCount {
+                expected: 2,
+                found: 1
+            })
+        );
+        assert_eq!(
+            "2x2 2/y".parse::<Board>(),
+            Err(ParsePositionErr::WrongRowLength {
+                row: 1,
+                expected: 2,
+                found: 1
+            })
+        );
+        assert_eq!(
+            "2x2 2/yx".parse::<Board>(),
+            Err(ParsePositionErr::InvalidCell { row: 1, found: 'x' })
+        );
+        assert_eq!(
+            "2x2 1y/r1".parse::<Board>(),
+            Err(ParsePositionErr::FloatingPiece { row: 0, col: 1 })
+        );
+        assert_eq!(
+            "2x2 2/yy y -".parse::<Game>().err(),
+            Some(ParsePositionErr::UnbalancedPieces { yellow: 2, red: 0 })
+        );
+        assert_eq!(
+            "2x2 2/yr y".parse::<Game>().err(),
+            Some(ParsePositionErr::MissingField("game state"))
+        );
+        assert_eq!(
+            "2x2 2/yr y - 0".parse::<Game>().err(),
+            Some(ParsePositionErr::InvalidConnect(String::from("0")))
+        );
+        assert_eq!(
+            "4x4 y3/y3/y3/yrrr r -".parse::<Game>().err(),
+            Some(ParsePositionErr::StateMismatch {
+                declared: '-',
+                actual: 'y',
+            })
+        );
+    }
+
+    #[test]
+    fn huge_numbers_are_rejected() {
+        assert!("6x7 99999999999999999999 y -".parse::<Game>().is_err());
+        assert_eq!(
+            "6x7 99999999999999999999/7/7/7/7/7 y -"
+                .parse::<Game>()
+                .err(),
+            Some(ParsePositionErr::RowTooLong {
+                row: 0,
+                expected: 7
+            })
+        );
+        assert_eq!(
+            "1x3 2y1".parse::<Board>(),
+            Err(ParsePositionErr::RowTooLong {
+                row: 0,
+                expected: 3
+            })
+        );
+        assert_eq!(
+            "1x3 yyyy".parse::<Board>(),
+            Err(ParsePositionErr::RowTooLong {
+                row: 0,
+                expected: 3
+            })
+        );
+        assert_eq!(
+            "99999999999999999999x7 7".parse::<Board>(),
+            Err(ParsePositionErr::InvalidSize(String::from(
+                "99999999999999999999x7"
+            )))
+        );
+        assert_eq!(
+            "1x100000 100000".parse::<Board>(),
+            Err(ParsePositionErr::InvalidSize(String::from("1x100000")))
+        );
+    }
+
+    #[test]
+    fn errors_are_readable() {
+        let err = "2x2 1y/r1".parse::<Board>().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "piece at row 1, column 2 has an empty cell below it"
+        );
+    }
+}
@@ -0,0 +1,61 @@
+/// Small xorshift generator, enough for tie breaking and random players
+/// without pulling a dependency in.
+#[derive(Debug, Clone)]
+pub(crate) struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub(crate) fn new(seed: u64) -> Self {
+        Rng {
+            state: seed ^ 0x2545_F491_4F6C_DD1D | 1,
+        }
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        let mut x = self.state;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.state = x;
+        x
+    }
+
+    /// Uniform value in `0..n`, `n` must not be zero.
+    pub(crate) fn below(&mut self, n: u64) -> u64 {
+        self.next_u64() % n
+    }
+
+    /// Uniform value in `-amplitude..=amplitude`.
+    pub(crate) fn noise(&mut self, amplitude: i32) -> i32 {
+        if amplitude <= 0 {
+            return 0;
+        }
+        let span = amplitude as u64 * 2 + 1;
+        self.below(span) as i32 - amplitude
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn same_seed_same_sequence() {
+        let mut a = Rng::new(7);
+        let mut b = Rng::new(7);
+        for _ in 0..10 {
+            assert_eq!(a.next_u64(), b.next_u64());
+        }
+    }
+
+    #[test]
+    fn noise_stays_in_range() {
+        let mut rng = Rng::new(0);
+        for _ in 0..1000 {
+            let n = rng.noise(3);
+            assert!((-3..=3).contains(&n));
+        }
+        assert_eq!(rng.noise(0), 0);
+    }
+}
@@ -0,0 +1,546 @@
+//! Perfect play solver for Connect 4.
+//!
+//! Positions are scored as in Pascal Pons' solver: a win is worth the number
+//! of the winner's pieces still in hand after the winning move plus one, a
+//! loss is the negated score of the opponent win and a draw is zero. Scores
+//! are found with a negamax using null window searches, bitboards, a
+//! transposition table and moves ordered by the threats they create.
+//!
+//! Any game of four in a row on a board that fits in a
+//! [`BitBoard`](crate::bitboard::BitBoard) can be solved, but only the
+//! standard 7x6 board and smaller are fast enough to be practical.
+
+use super::board::Move;
+use super::game::{Game, GameState};
+use super::transposition::{Bound, Entry, Replacement, TranspositionTable};
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Outcome {
+    Win,
+    Draw,
+    Loss,
+}
+
+/// Game theoretic value of a position, from the point of view of the player
+/// to move.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Solution {
+    pub outcome: Outcome,
+    /// Moves, counting both players, until the game ends with perfect play.
+    pub distance: usize,
+    pub score: i32,
+    pub best_move: Option<usize>,
+}
+
+pub const DEFAULT_TABLE_SIZE: usize = 1 << 20;
+
+pub fn can_solve(game: &Game) -> bool {
+    game.get_rules().is_classic()
+        && game.get_connect() == 4
+        && game.get_board().get_bitboard().is_some()
+}
+
+/// Solves `game` with a fresh [`Solver`].
+///
+/// # Panics
+///
+/// When the game isn't classic four in a row or the board is too big for a
+/// bitboard, see [`can_solve`].
+pub fn solve(game: &Game) -> Solution {
+    Solver::new(DEFAULT_TABLE_SIZE).solve(game)
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Position {
+    current: u64,
+    mask: u64,
+    moves: usize,
+}
+
+impl Position {
+    fn key(&self) -> u64 {
+        self.current + self.mask
+    }
+
+    fn play(&self, move_bit: u64) -> Position {
+        Position {
+            current: self.current ^ self.mask,
+            mask: self.mask | move_bit,
+            moves: self.moves + 1,
+        }
+    }
+}
+
+/// Keeps its transposition table between calls, so solving positions of
+/// the same game gets faster as the game goes on.
+pub struct Solver {
+    width: usize,
+    height: usize,
+    table: TranspositionTable,
+    nodes: u64,
+}
+
+impl Solver {
+    pub fn new(table_size: usize) -> Self {
+        Solver {
+            width: 0,
+            height: 0,
+            table: TranspositionTable::new(table_size, Replacement::Always),
+            nodes: 0,
+        }
+    }
+
+    /// Nodes explored by the last call to [`Solver::solve`].
+    pub fn get_nodes(&self) -> u64 {
+        self.nodes
+    }
+
+    /// # Panics
+    ///
+    /// When the game isn't classic four in a row or the board is too big
+    /// for a bitboard, see [`can_solve`].
+    pub fn solve(&mut self, game: &Game) -> Solution {
+        assert!(
+            game.get_rules().is_classic() && game.get_connect() == 4,
+            "only classic four in a row games can be solved"
+        );
+        let board = game.get_board();
+        let bits = board
+            .get_bitboard()
+            .expect("only boards that fit in a bitboard can be solved");
+        if (board.get_columns(), board.get_rows()) != (self.width, self.height) {
+            self.width = board.get_columns();
+            self.height = board.get_rows();
+            self.table.clear();
+        }
+        self.nodes = 0;
+
+        let p = Position {
+            current: bits.get_player_mask(game.current_player),
+            mask: bits.get_mask(),
+            moves: board.get_moves(),
+        };
+
+        match &game.state {
+            GameState::Winner(info) => {
+                let outcome = if info.player == game.current_player {
+                    Outcome::Win
+                } else {
+                    Outcome::Loss
+                };
+                return Solution {
+                    outcome,
+                    distance: 0,
+                    score: 0,
+                    best_move: None,
+                };
+            }
+            GameState::Tie => {
+                return Solution {
+                    outcome: Outcome::Draw,
+                    distance: 0,
+                    score: 0,
+                    best_move: None,
+                };
+            }
+            GameState::Playing => (),
+        }
+
+        let score = self.score(p);
+        let best_move = self.best_move(p, score);
+        self.solution(p, score, best_move)
+    }
+
+    fn solution(&self, p: Position, score: i32, best_move: Option<usize>) -> Solution {
+        let cells = self.width * self.height;
+        let (outcome, distance) = if score == 0 {
+            (Outcome::Draw, cells - p.moves)
+        } else {
+            // The winner's last move is one of two move numbers giving the
+            // same score, the right one depends on whose turn it is.
+            let high = cells + 1 - 2 * score.unsigned_abs() as usize;
+            let winner_parity = if score > 0 {
+                p.moves % 2
+            } else {
+                (p.moves + 1) % 2
+            };
+            let last = if high % 2 == winner_parity {
+                high
+            } else {
+                high - 1
+            };
+            let outcome = if score > 0 {
+                Outcome::Win
+            } else {
+                Outcome::Loss
+            };
+            (outcome, last - p.moves + 1)
+        };
+        Solution {
+            outcome,
+            distance,
+            score,
+            best_move,
+        }
+    }
+
+    fn score(&mut self, p: Position) -> i32 {
+        let cells = self.width * self.height;
+        if self.can_win_next(p) {
+            return ((cells + 1 - p.moves) / 2) as i32;
+        }
+
+        let mut min = -((cells - p.moves) as i32) / 2;
+        let mut max = ((cells + 1 - p.moves) / 2) as i32;
+        while min < max {
+            let mut med = min + (max - min) / 2;
+            if med <= 0 && min / 2 < med {
+                med = min / 2;
+            } else if med >= 0 && max / 2 > med {
+                med = max / 2;
+            }
+            let r = self.negamax(p, med, med + 1);
+            if r <= med {
+                max = r;
+            } else {
+                min = r;
+            }
+        }
+        min
+    }
+
+    /// Finds a move reaching `score`, checking each move with a null window
+    /// search, which is much cheaper than solving every child.
+    fn best_move(&mut self, p: Position, score: i32) -> Option<usize> {
+        let cells = self.width * self.height;
+        let possible = self.possible(p);
+        let columns = self.column_order();
+        for &col in &columns {
+            let move_bit = possible & self.column_mask(col);
+            if move_bit == 0 {
+                continue;
+            }
+            if self.winning_position(p.current, p.mask) & move_bit != 0 {
+                return Some(col);
+            }
+        }
+
+        let mut fallback = None;
+        for &col in &columns {
+            let move_bit = possible & self.column_mask(col);
+            if move_bit == 0 {
+                continue;
+            }
+            fallback = fallback.or(Some(col));
+            let child = p.play(move_bit);
+            let child_score = if child.moves == cells {
+                0
+            } else if self.can_win_next(child) {
+                -(((cells + 1 - child.moves) / 2) as i32)
+            } else {
+                -self.negamax(child, -score, -score + 1)
+            };
+            if child_score >= score {
+                return Some(col);
+            }
+        }
+        fallback
+    }
+
+    fn negamax(&mut self, p: Position, alpha: i32, beta: i32) -> i32 {
+        self.nodes += 1;
+        let cells = self.width * self.height;
+        let (mut alpha, mut beta) = (alpha, beta);
+
+        let next = self.non_losing_moves(p);
+        if next == 0 {
+            return -((cells - p.moves) as i32) / 2;
+        }
+        if p.moves + 2 >= cells {
+            return 0;
+        }
+
+        let mut min = -((cells - 2 - p.moves) as i32) / 2;
+        let mut max = ((cells - 1 - p.moves) / 2) as i32;
+        let key = p.key();
+        if let Some(entry) = self.table.probe(key) {
+            match entry.bound {
+                Bound::Lower => min = min.max(entry.score),
+                Bound::Upper => max = max.min(entry.score),
+                Bound::Exact => return entry.score,
+            }
+        }
+        if alpha < min {
+            alpha = min;
+            if alpha >= beta {
+                return alpha;
+            }
+        }
+        if beta > max {
+            beta = max;
+            if alpha >= beta {
+                return beta;
+            }
+        }
+
+        for move_bit in self.sorted_moves(p, next) {
+            let score = -self.negamax(p.play(move_bit), -beta, -alpha);
+            if score >= beta {
+                self.store(key, score, Bound::Lower);
+                return score;
+            }
+            if score > alpha {
+                alpha = score;
+            }
+        }
+        self.store(key, alpha, Bound::Upper);
+        alpha
+    }
+
+    fn store(&mut self, key: u64, score: i32, bound: Bound) {
+        self.table.store(Entry {
+            key,
+            depth: 0,
+            score,
+            bound,
+            best_move: Move::Drop(0),
+        });
+    }
+
+    /// Moves sorted by how many winning cells they leave, ties broken by
+    /// the distance to the center column.
+    fn sorted_moves(&self, p: Position, next: u64) -> Vec<u64> {
+        let mut moves: Vec<(u64, u32)> = Vec::with_capacity(self.width);
+        for col in self.column_order() {
+            let move_bit = next & self.column_mask(col);
+            if move_bit == 0 {
+                continue;
+            }
+            let threats = self
+                .winning_position(p.current | move_bit, p.mask)
+                .count_ones();
+            let pos = moves
+                .iter()
+                .position(|&(_, t)| t < threats)
+                .unwrap_or(moves.len());
+            moves.insert(pos, (move_bit, threats));
+        }
+        moves.into_iter().map(|(m, _)| m).collect()
+    }
+
+    fn column_order(&self) -> Vec<usize> {
+        let mut columns: Vec<usize> = (0..self.width).collect();
+        let center = (self.width - 1) as f32 / 2.0;
+        columns.sort_by(|a, b| {
+            let da = (*a as f32 - center).abs();
+            let db = (*b as f32 - center).abs();
+            da.partial_cmp(&db).expect("distances are finite")
+        });
+        columns
+    }
+
+    fn can_win_next(&self, p: Position) -> bool {
+        self.winning_position(p.current, p.mask) & self.possible(p) != 0
+    }
+
+    /// Playable cells that do not let the opponent win right away. Empty
+    /// when every move loses.
+    fn non_losing_moves(&self, p: Position) -> u64 {
+        let mut possible = self.possible(p);
+        let opponent_win = self.winning_position(p.current ^ p.mask, p.mask);
+        let forced = possible & opponent_win;
+        if forced != 0 {
+            if forced & (forced - 1) != 0 {
+                return 0;
+            }
+            possible = forced;
+        }
+        possible & !(opponent_win >> 1)
+    }
+
+    fn possible(&self, p: Position) -> u64 {
+        (p.mask + self.bottom_mask()) & self.board_mask()
+    }
+
+    /// Empty cells, playable or not, that would complete four in a row for
+    /// the player owning `position`.
+    fn winning_position(&self, position: u64, mask: u64) -> u64 {
+        let h = self.height as u32;
+        let mut r = (position << 1) & (position << 2) & (position << 3);
+
+        for &dir in &[h + 1, h, h + 2] {
+            let p = (position << dir) & (position << (2 * dir));
+            r |= p & (position << (3 * dir));
+            r |= p & (position >> dir);
+            let p = (position >> dir) & (position >> (2 * dir));
+            r |= p & (position << dir);
+            r |= p & (position >> (3 * dir));
+        }
+
+        r & (self.board_mask() ^ mask)
+    }
+
+    fn bottom_mask(&self) -> u64 {
+        (0..self.width).fold(0, |m, c| m | 1 << (c * (self.height + 1)))
+    }
+
+    fn board_mask(&self) -> u64 {
+        self.bottom_mask() * ((1 << self.height) - 1)
+    }
+
+    fn column_mask(&self, col: usize) -> u64 {
+        ((1 << self.height) - 1) << (col * (self.height + 1))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::board::{Board, Move, PlayErr, Player};
+    use crate::game::{Classic, Cylinder, FirstColumnBonus, PopOut, RuleState, Rules};
+    use crate::validator;
+    use std::sync::Arc;
+
+    /// Pop Out under the name of the classic rules.
+    struct Impostor;
+
+    impl Rules for Impostor {
+        fn name(&self) -> &str {
+            "classic"
+        }
+
+        fn valid_moves(&self, board: &Board, state: &RuleState, player: Player) -> Vec<Move> {
+            PopOut.valid_moves(board, state, player)
+        }
+
+        fn play(
+            &self,
+            board: &mut Board,
+            state: &mut RuleState,
+            connect: usize,
+            m: Move,
+            player: Player,
+        ) -> Result<(), PlayErr> {
+            PopOut.play(board, state, connect, m, player)
+        }
+
+        fn state_after(
+            &self,
+            board: &Board,
+            state: &RuleState,
+            connect: usize,
+            m: Move,
+            player: Player,
+        ) -> GameState {
+            PopOut.state_after(board, state, connect, m, player)
+        }
+    }
+
+    #[test]
+    fn only_classic_rules_can_be_solved() {
+        let solvable =
+            |rules: Arc<dyn Rules>| can_solve(&Game::with_rules(6, 7, 4, rules, Player::Yellow));
+        assert!(solvable(Arc::new(Classic)));
+        assert!(!solvable(Arc::new(PopOut)));
+        assert!(!solvable(Arc::new(Impostor)));
+        assert!(!solvable(Arc::new(FirstColumnBonus)));
+        assert!(!solvable(Arc::new(Cylinder::new(Classic))));
+    }
+
+    /// Plain negamax over every move, only usable on tiny boards.
+    fn brute_force(game: &mut Game) -> i32 {
+        let board = game.get_board();
+        let cells = board.get_rows() * board.get_columns();
+        let moves = board.get_moves();
+        let mut best = None;
+        for m in board.get_valid_moves() {
+            game.play(m).expect("valid move");
+            let score = if validator::get_winner(game.get_board(), 4).is_some() {
+                ((cells + 1 - moves) / 2) as i32
+            } else if game.get_board().is_board_full() {
+                0
+            } else {
+                -brute_force(game)
+            };
+            game.undo();
+            best = Some(best.map_or(score, |b: i32| b.max(score)));
+        }
+        best.unwrap_or(0)
+    }
+
+    #[test]
+    fn matches_brute_force_on_small_boards() {
+        let openings: &[(usize, usize, &[usize])] = &[
+            (3, 4, &[]),
+            (4, 4, &[0, 3, 1, 2]),
+            (4, 4, &[1, 2, 2, 1, 3]),
+            (4, 4, &[3, 3, 3, 0, 0]),
+            (4, 4, &[1, 1, 2, 2]),
+            (4, 4, &[2, 2, 1, 1, 0]),
+            (4, 4, &[0, 1, 2, 3, 0, 1]),
+        ];
+        let mut solver = Solver::new(1 << 16);
+        for &(rows, cols, opening) in openings {
+            let mut game =
+                Game::from_moves(rows, cols, Player::Yellow, opening).expect("valid opening");
+            let expected = brute_force(&mut game);
+            let solution = solver.solve(&game);
+            assert_eq!(solution.score, expected, "opening {:?}", opening);
+
+            let best = solution.best_move.expect("game is not over");
+            game.play(best).expect("best move is valid");
+            let after = if game.state == GameState::Playing {
+                -solver.solve(&game).score
+            } else {
+                solution.score
+            };
+            assert_eq!(after, solution.score, "best move of {:?}", opening);
+        }
+    }
+
+    #[test]
+    fn finds_immediate_win() {
+        let game = Game::from_notation(6, 7, Player::Yellow, "121212").expect("valid moves");
+        let solution = solve(&game);
+
+        assert_eq!(solution.outcome, Outcome::Win);
+        assert_eq!(solution.distance, 1);
+        assert_eq!(solution.best_move, Some(0));
+        assert_eq!(solution.score, 18);
+    }
+
+    #[test]
+    fn sees_unstoppable_loss() {
+        let game = Game::from_notation(6, 7, Player::Yellow, "27374").expect("valid moves");
+        let solution = solve(&game);
+
+        assert_eq!(solution.outcome, Outcome::Loss);
+        assert_eq!(solution.distance, 2);
+    }
+
+    #[test]
+    fn solves_late_standard_position() {
+        let game = Game::from_notation(6, 7, Player::Yellow, "111111222222666666777777")
+            .expect("valid moves");
+        let mut solver = Solver::new(DEFAULT_TABLE_SIZE);
+        let solution = solver.solve(&game);
+
+        let mut after = game.clone();
+        after
+            .play(solution.best_move.expect("game is not over"))
+            .expect("best move is valid");
+        let reply = solver.solve(&after);
+        assert_eq!(reply.score, -solution.score);
+        assert_eq!(reply.distance + 1, solution.distance);
+    }
+
+    #[test]
+    fn finished_games_have_no_move() {
+        let game = Game::from_notation(6, 7, Player::Yellow, "1212121").expect("valid moves");
+        let solution = solve(&game);
+
+        assert_eq!(solution.outcome, Outcome::Loss);
+        assert_eq!(solution.best_move, None);
+        assert_eq!(solution.distance, 0);
+    }
+}
@@ -0,0 +1,398 @@
+//! Threat based evaluation.
+//!
+//! A threat is an empty cell that would complete a line, four in a row in
+//! the standard game, for a player. Threats are what decide Connect 4
+//! games: one that can be played right away wins or has to be blocked, two
+//! stacked in the same column can't both be stopped, and by zugzwang the
+//! first player wants its threats on odd rows, counting from the bottom,
+//! and the second player on even rows.
+
+use super::ai::Evaluator;
+use super::board::{Board, Player};
+use super::game::Game;
+
+/// Points for each feature found for a player. The evaluation is the sum of
+/// the player's features minus the sum of the opponent's.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct ThreatWeights {
+    /// Points for a window of four cells holding only pieces of the player,
+    /// indexed by how many it holds. Windows of other line lengths have the
+    /// count scaled to the four entries.
+    pub window: [i32; 4],
+    pub threat: i32,
+    /// Extra points for a threat on a row of the player's parity.
+    pub good_threat: i32,
+    /// A line one piece short of winning, three in the standard game, with
+    /// an empty cell on both ends.
+    pub open_three: i32,
+    /// Two threats of the player on top of each other.
+    pub double_threat: i32,
+    /// The player is about to move and a threat is playable, or the
+    /// opponent is about to move and can't block every playable threat.
+    pub immediate_win: i32,
+    /// The player is about to move and has to block a playable threat.
+    pub must_block: i32,
+}
+
+impl Default for ThreatWeights {
+    fn default() -> Self {
+        ThreatWeights {
+            window: [0, 1, 3, 6],
+            threat: 12,
+            good_threat: 20,
+            open_three: 30,
+            double_threat: 150,
+            immediate_win: 1000,
+            must_block: 40,
+        }
+    }
+}
+
+impl Evaluator for ThreatWeights {
+    fn evaluate(&self, game: &Game, player: Player) -> i32 {
+        evaluate(game, player, self)
+    }
+}
+
+/// Scores `game` for `player`, see [`ThreatWeights`].
+pub fn evaluate(game: &Game, player: Player, weights: &ThreatWeights) -> i32 {
+    let board = game.get_board();
+    let to_move = game.current_player;
+    let threats = Threats::new(board, game.get_connect());
+
+    let own_now = threats.playable(board, to_move);
+    let other_now = threats.playable(board, to_move.opponent());
+    let tempo = if own_now > 0 {
+        weights.immediate_win
+    } else if other_now > 1 {
+        -weights.immediate_win
+    } else if other_now == 1 {
+        -weights.must_block
+    } else {
+        0
+    };
+    let tempo = if to_move == player { tempo } else { -tempo };
+
+    let first = if board.get_moves().is_multiple_of(2) {
+        to_move
+    } else {
+        to_move.opponent()
+    };
+    tempo + threats.features(board, player, first, weights)
+        - threats.features(board, player.opponent(), first, weights)
+}
+
+const DIRECTIONS: [(isize, isize); 4] = [(0, 1), (1, 0), (1, 1), (1, -1)];
+
+/// Threat cells of both players, row by row from the top like the board.
+struct Threats {
+    cells: [Vec<bool>; 2],
+    connect: usize,
+}
+
+impl Threats {
+    fn new(board: &Board, connect: usize) -> Self {
+        let size = board.get_rows() * board.get_columns();
+        let mut threats = Threats {
+            cells: [vec![false; size], vec![false; size]],
+            connect,
+        };
+        for_each_window(board, connect, |window| {
+            let pieces: Vec<Option<Player>> = window
+                .iter()
+                .map(|&(r, c)| board.get_piece_at(r, c))
+                .collect();
+            let empty: Vec<&(usize, usize)> = window
+                .iter()
+                .zip(&pieces)
+                .filter(|(_, p)| p.is_none())
+                .map(|(cell, _)| cell)
+                .collect();
+            if let [&(r, c)] = empty[..] {
+                for &player in &[Player::Yellow, Player::Red] {
+                    if pieces.iter().filter(|&&p| p == Some(player)).count() == connect - 1 {
+                        threats.cells[player.index()][r * board.get_columns() + c] = true;
+                    }
+                }
+            }
+        });
+        threats
+    }
+
+    fn is_threat(&self, board: &Board, player: Player, row: usize, col: usize) -> bool {
+        self.cells[player.index()][row * board.get_columns() + col]
+    }
+
+    /// Threats of `player` that can be played on the next move.
+    fn playable(&self, board: &Board, player: Player) -> usize {
+        (0..board.get_columns())
+            .filter_map(|col| next_row(board, col).map(|row| (row, col)))
+            .filter(|&(row, col)| self.is_threat(board, player, row, col))
+            .count()
+    }
+
+    fn features(
+        &self,
+        board: &Board,
+        player: Player,
+        first: Player,
+        weights: &ThreatWeights,
+    ) -> i32 {
+        let rows = board.get_rows();
+        let good_parity = if player == first { 1 } else { 0 };
+        let mut score = 0;
+        for row in 0..rows {
+            for col in 0..board.get_columns() {
+                if !self.is_threat(board, player, row, col) {
+                    continue;
+                }
+                score += weights.threat;
+                if (rows - row) % 2 == good_parity {
+                    score += weights.good_threat;
+                }
+                if row > 0 && self.is_threat(board, player, row - 1, col) {
+                    score += weights.double_threat;
+                }
+            }
+        }
+
+        let connect = self.connect;
+        for_each_window(board, connect, |window| {
+            let mut count = 0;
+            for &(r, c) in window {
+                match board.get_piece_at(r, c) {
+                    Some(p) if p == player => count += 1,
+                    Some(_) => return,
+                    None => (),
+                }
+            }
+            if count < connect {
+                score += weights.window[count * 3 / (connect - 1).max(1)];
+            }
+        });
+
+        for_each_window(board, connect + 1, |window| {
+            let is_open_three = window.iter().enumerate().all(|(i, &(r, c))| {
+                let piece = board.get_piece_at(r, c);
+                if i == 0 || i == connect {
+                    piece.is_none()
+                } else {
+                    piece == Some(player)
+                }
+            });
+            // Vertical threes are never open below.
+            if is_open_three && window[0].1 != window[1].1 {
+                score += weights.open_three;
+            }
+        });
+        score
+    }
+}
+
+/// Calls `f` with the cells of every line of `len` cells on the board. On a
+/// cylinder lines cross the sides, except rows as long as the board is wide,
+/// which would otherwise be found once per column.
+fn for_each_window<F: FnMut(&[(usize, usize)])>(board: &Board, len: usize, mut f: F) {
+    let rows = board.get_rows() as isize;
+    let cols = board.get_columns() as isize;
+    let mut window = Vec::with_capacity(len);
+    for row in 0..rows {
+        for col in 0..cols {
+            for &(dr, dc) in &DIRECTIONS {
+                let wraps = board.is_cylinder() && (dr != 0 || (len as isize) < cols);
+                window.clear();
+                for i in 0..len as isize {
+                    let (r, mut c) = (row + i * dr, col + i * dc);
+                    if wraps {
+                        c = c.rem_euclid(cols);
+                    }
+                    if r < 0 || r >= rows || c < 0 || c >= cols {
+                        break;
+                    }
+                    window.push((r as usize, c as usize));
+                }
+                if window.len() == len {
+                    f(&window);
+                }
+            }
+        }
+    }
+}
+
+/// Row the next piece dropped in `col` lands on, if the column isn't full.
+fn next_row(board: &Board, col: usize) -> Option<usize> {
+    (0..board.get_rows())
+        .rev()
+        .find(|&row| board.get_piece_at(row, col).is_none())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::board::PlayErr;
+    use crate::game::{Classic, Cylinder};
+    use std::sync::Arc;
+
+    fn none() -> ThreatWeights {
+        ThreatWeights {
+            window: [0, 0, 0, 0],
+            threat: 0,
+            good_threat: 0,
+            open_three: 0,
+            double_threat: 0,
+            immediate_win: 0,
+            must_block: 0,
+        }
+    }
+
+    #[test]
+    fn empty_board_is_even() {
+        let game = Game::new(Player::Yellow);
+        assert_eq!(
+            evaluate(&game, Player::Yellow, &ThreatWeights::default()),
+            0
+        );
+    }
+
+    #[test]
+    fn evaluation_is_symmetric() -> Result<(), PlayErr> {
+        let game = Game::from_moves(6, 7, Player::Yellow, &[3, 3, 2, 4, 4, 2])?;
+        let weights = ThreatWeights::default();
+        assert_eq!(
+            evaluate(&game, Player::Yellow, &weights),
+            -evaluate(&game, Player::Red, &weights)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn playable_threat_of_the_player_to_move_wins() -> Result<(), PlayErr> {
+        let game = Game::from_moves(6, 7, Player::Yellow, &[0, 6, 1, 6, 2, 5])?;
+        let weights = ThreatWeights {
+            immediate_win: 1000,
+            ..none()
+        };
+        assert_eq!(evaluate(&game, Player::Yellow, &weights), 1000);
+        Ok(())
+    }
+
+    #[test]
+    fn single_playable_threat_must_be_blocked() -> Result<(), PlayErr> {
+        let game = Game::from_moves(6, 7, Player::Yellow, &[0, 6, 1, 6, 2])?;
+        let weights = ThreatWeights {
+            immediate_win: 1000,
+            must_block: 40,
+            ..none()
+        };
+        assert_eq!(evaluate(&game, Player::Red, &weights), -40);
+        Ok(())
+    }
+
+    #[test]
+    fn two_playable_threats_cannot_be_blocked() -> Result<(), PlayErr> {
+        // Yellow has 1, 2 and 3 on the bottom row, both ends are open.
+        let game = Game::from_moves(6, 7, Player::Yellow, &[1, 6, 2, 6, 3])?;
+        let weights = ThreatWeights {
+            immediate_win: 1000,
+            open_three: 30,
+            ..none()
+        };
+        assert_eq!(evaluate(&game, Player::Yellow, &weights), 1000 + 30);
+        Ok(())
+    }
+
+    #[test]
+    fn threats_on_the_right_parity_score_more() -> Result<(), PlayErr> {
+        // Yellow threatens the bottom row, an odd one, which is good for
+        // the first player only.
+        let game = Game::from_moves(6, 7, Player::Yellow, &[0, 6, 1, 6, 2, 5])?;
+        let board = game.get_board();
+        let threats = Threats::new(board, 4);
+        assert!(threats.is_threat(board, Player::Yellow, 5, 3));
+
+        let weights = ThreatWeights {
+            threat: 10,
+            good_threat: 5,
+            ..none()
+        };
+        assert_eq!(
+            threats.features(board, Player::Yellow, Player::Yellow, &weights),
+            15
+        );
+        assert_eq!(
+            threats.features(board, Player::Yellow, Player::Red, &weights),
+            10
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn stacked_threats_are_double_threats() -> Result<(), PlayErr> {
+        let mut board = Board::new(6, 7);
+        for &(col, player) in &[
+            (0, Player::Red),
+            (1, Player::Red),
+            (2, Player::Red),
+            (0, Player::Yellow),
+            (1, Player::Yellow),
+            (2, Player::Yellow),
+            (0, Player::Yellow),
+            (1, Player::Yellow),
+            (2, Player::Yellow),
+        ] {
+            board.play(col, player)?;
+        }
+        // Yellow completes rows 2 and 3 from the bottom by playing column 3
+        // twice.
+        let threats = Threats::new(&board, 4);
+        assert!(threats.is_threat(&board, Player::Yellow, 4, 3));
+        assert!(threats.is_threat(&board, Player::Yellow, 3, 3));
+
+        let weights = ThreatWeights {
+            double_threat: 100,
+            ..none()
+        };
+        assert_eq!(
+            threats.features(&board, Player::Yellow, Player::Yellow, &weights),
+            100
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn threats_follow_the_connect_length() -> Result<(), PlayErr> {
+        let mut game = Game::with_connect(6, 7, 3, Player::Yellow);
+        for &col in &[2, 6, 3] {
+            game.play(col)?;
+        }
+        let threats = Threats::new(game.get_board(), game.get_connect());
+        assert!(threats.is_threat(game.get_board(), Player::Yellow, 5, 1));
+        assert!(threats.is_threat(game.get_board(), Player::Yellow, 5, 4));
+
+        let weights = ThreatWeights {
+            immediate_win: 1000,
+            ..none()
+        };
+        assert_eq!(evaluate(&game, Player::Yellow, &weights), 1000);
+        Ok(())
+    }
+
+    #[test]
+    fn threats_wrap_around_cylinders() -> Result<(), PlayErr> {
+        let rules = Arc::new(Cylinder::new(Classic));
+        let mut game = Game::with_rules(6, 7, 4, rules, Player::Yellow);
+        for &col in &[5, 3, 6, 3, 0] {
+            game.play(col)?;
+        }
+        let threats = Threats::new(game.get_board(), game.get_connect());
+        assert!(threats.is_threat(game.get_board(), Player::Yellow, 5, 1));
+        assert!(threats.is_threat(game.get_board(), Player::Yellow, 5, 4));
+
+        let weights = ThreatWeights {
+            immediate_win: 1000,
+            ..none()
+        };
+        assert_eq!(evaluate(&game, Player::Yellow, &weights), 1000);
+        Ok(())
+    }
+}
@@ -0,0 +1,170 @@
+use super::board::Move;
+
+/// How the score stored in an entry relates to the real score of the
+/// position, depending on whether the search was cut by alpha or beta.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Bound {
+    Exact,
+    Lower,
+    Upper,
+}
+
+/// What to do when a new entry maps to a slot that is already taken by a
+/// different position.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Replacement {
+    Always,
+    DepthPreferred,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Entry {
+    pub key: u64,
+    pub depth: usize,
+    pub score: i32,
+    pub bound: Bound,
+    pub best_move: Move,
+}
+
+pub struct TranspositionTable {
+    entries: Vec<Option<Entry>>,
+    replacement: Replacement,
+}
+
+pub const DEFAULT_SIZE: usize = 1 << 16;
+
+impl TranspositionTable {
+    /// Creates a table holding up to `size` entries.
+    pub fn new(size: usize, replacement: Replacement) -> Self {
+        TranspositionTable {
+            entries: vec![None; size.max(1)],
+            replacement,
+        }
+    }
+
+    pub fn probe(&self, key: u64) -> Option<Entry> {
+        match self.entries[self.index(key)] {
+            Some(entry) if entry.key == key => Some(entry),
+            _ => None,
+        }
+    }
+
+    pub fn store(&mut self, entry: Entry) {
+        let idx = self.index(entry.key);
+        let replace = match (self.replacement, self.entries[idx]) {
+            (Replacement::DepthPreferred, Some(old)) => {
+                old.key == entry.key || old.depth <= entry.depth
+            }
+            _ => true,
+        };
+        if replace {
+            self.entries[idx] = Some(entry);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        for e in self.entries.iter_mut() {
+            *e = None;
+        }
+    }
+
+    pub fn get_size(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Keys are mixed first, so keys that only differ in their high bits,
+    /// like raw bitboards, still spread over the whole table.
+    fn index(&self, key: u64) -> usize {
+        let h = (key ^ (key >> 29)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        ((h ^ (h >> 32)) % self.entries.len() as u64) as usize
+    }
+}
+
+impl Default for TranspositionTable {
+    fn default() -> Self {
+        TranspositionTable::new(DEFAULT_SIZE, Replacement::DepthPreferred)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn entry(key: u64, depth: usize) -> Entry {
+        Entry {
+            key,
+            depth,
+            score: 10,
+            bound: Bound::Exact,
+            best_move: Move::Drop(3),
+        }
+    }
+
+    fn colliding_key(table: &TranspositionTable, key: u64) -> u64 {
+        (key + 1..)
+            .find(|&k| table.index(k) == table.index(key))
+            .expect("a small table has collisions")
+    }
+
+    #[test]
+    fn probe_returns_stored_entry() {
+        let mut table = TranspositionTable::new(8, Replacement::Always);
+        table.store(entry(42, 2));
+
+        assert_eq!(table.probe(42), Some(entry(42, 2)));
+        assert_eq!(table.probe(43), None);
+    }
+
+    #[test]
+    fn probe_ignores_colliding_keys() {
+        let mut table = TranspositionTable::new(8, Replacement::Always);
+        table.store(entry(1, 2));
+
+        assert_eq!(table.probe(colliding_key(&table, 1)), None);
+    }
+
+    #[test]
+    fn always_replaces_on_collision() {
+        let mut table = TranspositionTable::new(8, Replacement::Always);
+        let other = colliding_key(&table, 1);
+        table.store(entry(1, 5));
+        table.store(entry(other, 1));
+
+        assert_eq!(table.probe(1), None);
+        assert_eq!(table.probe(other), Some(entry(other, 1)));
+    }
+
+    #[test]
+    fn depth_preferred_keeps_deeper_entries() {
+        let mut table = TranspositionTable::new(8, Replacement::DepthPreferred);
+        let other = colliding_key(&table, 1);
+        table.store(entry(1, 5));
+        table.store(entry(other, 1));
+        assert_eq!(table.probe(1), Some(entry(1, 5)));
+
+        table.store(entry(other, 6));
+        assert_eq!(table.probe(other), Some(entry(other, 6)));
+
+        table.store(entry(other, 2));
+        assert_eq!(table.probe(other), Some(entry(other, 2)));
+    }
+
+    #[test]
+    fn keys_differing_in_high_bits_spread() {
+        let table = TranspositionTable::new(1 << 10, Replacement::Always);
+        let slots: std::collections::HashSet<usize> =
+            (0..64).map(|i| table.index(i << 40)).collect();
+
+        assert!(slots.len() > 32);
+    }
+
+    #[test]
+    fn clear_removes_everything() {
+        let mut table = TranspositionTable::default();
+        table.store(entry(7, 1));
+        table.clear();
+
+        assert_eq!(table.probe(7), None);
+        assert_eq!(table.get_size(), DEFAULT_SIZE);
+    }
+}
@@ -1,20 +1,220 @@
 use super::board;
 use super::board_iterators;
 
-pub fn get_winner(board: &board::Board) -> Option<board::Player> {
-    find_winner_in_rows(board)
-        .or_else(|| find_winner_in_cols(board))
-        .or_else(|| find_winner_in_diagonals(board))
+/// Direction of a line of pieces.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Direction {
+    Horizontal,
+    Vertical,
+    /// From the bottom left to the top right.
+    Rising,
+    /// From the top left to the bottom right.
+    Falling,
 }
 
-fn find_winner_in_rows(board: &board::Board) -> Option<board::Player> {
-    let rit = board_iterators::RowIterator::new(board);
+/// Cells of a line of pieces, as (row, col) from one end to the other.
+#[derive(Debug, PartialEq, Clone)]
+pub struct WinLine {
+    pub direction: Direction,
+    pub cells: Vec<(usize, usize)>,
+}
+
+/// The winner of a game and every line that won it, usually one but a
+/// single move can complete several.
+#[derive(Debug, PartialEq, Clone)]
+pub struct WinInfo {
+    pub player: board::Player,
+    pub lines: Vec<WinLine>,
+}
+
+impl WinInfo {
+    /// Cells of every winning line, each listed once.
+    pub fn get_cells(&self) -> Vec<(usize, usize)> {
+        let mut cells = Vec::new();
+        for cell in self.lines.iter().flat_map(|l| l.cells.iter()) {
+            if !cells.contains(cell) {
+                cells.push(*cell);
+            }
+        }
+        cells
+    }
+}
+
+/// Finds a player with `connect` pieces in a row, along with all of the
+/// player's lines that are long enough.
+pub fn get_winner(board: &board::Board, connect: usize) -> Option<WinInfo> {
+    let player = match board.get_bitboard() {
+        Some(bits) => bits.get_winner(connect),
+        None => find_winner_in_rows(board, connect)
+            .or_else(|| find_winner_in_cols(board, connect))
+            .or_else(|| find_winner_in_diagonals(board, connect)),
+    }?;
+
+    let mut lines = Vec::new();
+    for row in 0..board.get_rows() {
+        for col in 0..board.get_columns() {
+            if board.get_piece_at(row, col) != Some(player) {
+                continue;
+            }
+            for &(direction, (dr, dc)) in &DIRECTIONS {
+                // Only lines starting on this cell, so each is found once. A
+                // row filled all the way around a cylinder has no start, so
+                // it is taken from the first column.
+                let back = run_length(board, player, (row, col), (-dr, -dc));
+                if back > 0 && !(back == run_limit(board, (dr, dc)) && col == 0) {
+                    continue;
+                }
+                let length = run_length(board, player, (row, col), (dr, dc)) + 1;
+                if length >= connect {
+                    lines.push(line(board, direction, (row, col), 0, length - 1));
+                }
+            }
+        }
+    }
+    Some(WinInfo { player, lines })
+}
+
+const DIRECTIONS: [(Direction, (isize, isize)); 4] = [
+    (Direction::Horizontal, (0, 1)),
+    (Direction::Vertical, (1, 0)),
+    (Direction::Falling, (1, 1)),
+    (Direction::Rising, (1, -1)),
+];
+
+/// Looks for lines of at least `connect` pieces through the piece at `row`,
+/// `col`. Any line made by a move goes through the piece just played, so
+/// checking it is enough after each move and much cheaper than scanning the
+/// whole board.
+pub fn get_winner_at(
+    board: &board::Board,
+    row: usize,
+    col: usize,
+    connect: usize,
+) -> Option<WinInfo> {
+    let player = board.get_piece_at(row, col)?;
+    let mut lines = Vec::new();
+    for &(direction, (dr, dc)) in &DIRECTIONS {
+        let back = run_length(board, player, (row, col), (-dr, -dc));
+        let forward = run_length(board, player, (row, col), (dr, dc));
+        // Around a full cylinder row both runs reach every other piece.
+        let forward = forward.min(run_limit(board, (dr, dc)) - back);
+        if back + 1 + forward >= connect {
+            lines.push(line(board, direction, (row, col), back, forward));
+        }
+    }
+    if lines.is_empty() {
+        None
+    } else {
+        Some(WinInfo { player, lines })
+    }
+}
+
+/// Looks for lines of at least `connect` pieces through any piece of `col`,
+/// where a pop just shifted every piece. A pop can complete lines of both
+/// players at once, in which case `mover`, the player who popped, wins.
+pub fn get_winner_in_column(
+    board: &board::Board,
+    col: usize,
+    connect: usize,
+    mover: board::Player,
+) -> Option<WinInfo> {
+    let mut wins: Vec<WinInfo> = Vec::new();
+    for row in 0..board.get_rows() {
+        let found = match get_winner_at(board, row, col, connect) {
+            Some(found) => found,
+            None => continue,
+        };
+        match wins.iter_mut().find(|w| w.player == found.player) {
+            Some(win) => {
+                for line in found.lines {
+                    // Vertical lines are found once for each of their pieces.
+                    if !win.lines.contains(&line) {
+                        win.lines.push(line);
+                    }
+                }
+            }
+            None => wins.push(found),
+        }
+    }
+    let mover_index = wins.iter().position(|w| w.player == mover);
+    match mover_index {
+        Some(i) => Some(wins.swap_remove(i)),
+        None => wins.pop(),
+    }
+}
+
+/// The line along `direction` going from `back` cells before `start` to
+/// `forward` cells after it.
+fn line(
+    board: &board::Board,
+    direction: Direction,
+    start: (usize, usize),
+    back: usize,
+    forward: usize,
+) -> WinLine {
+    let (_, (dr, dc)) = DIRECTIONS
+        .iter()
+        .find(|(d, _)| *d == direction)
+        .expect("every direction has a step");
+    let cells = (-(back as isize)..=forward as isize)
+        .map(|i| {
+            (
+                (start.0 as isize + i * dr) as usize,
+                wrap_column(board, start.1 as isize + i * dc) as usize,
+            )
+        })
+        .collect();
+    WinLine { direction, cells }
+}
+
+/// Pieces of `player` following `start` along `dir`, not counting `start`.
+fn run_length(
+    board: &board::Board,
+    player: board::Player,
+    start: (usize, usize),
+    dir: (isize, isize),
+) -> usize {
+    let limit = run_limit(board, dir);
+    let mut length = 0;
+    while length < limit {
+        let step = length as isize + 1;
+        let r = start.0 as isize + step * dir.0;
+        let c = wrap_column(board, start.1 as isize + step * dir.1);
+        if r < 0 || c < 0 || board.get_piece_at(r as usize, c as usize) != Some(player) {
+            break;
+        }
+        length += 1;
+    }
+    length
+}
+
+/// Longest run `run_length` can find along `dir`. Rows of a cylinder have no
+/// end, so their runs stop before coming back to the starting piece.
+fn run_limit(board: &board::Board, dir: (isize, isize)) -> usize {
+    if board.is_cylinder() && dir.0 == 0 {
+        board.get_columns() - 1
+    } else {
+        usize::MAX
+    }
+}
+
+/// Brings `col` back onto the board if it wraps around a cylinder.
+fn wrap_column(board: &board::Board, col: isize) -> isize {
+    if board.is_cylinder() {
+        col.rem_euclid(board.get_columns() as isize)
+    } else {
+        col
+    }
+}
+
+fn find_winner_in_rows(board: &board::Board, connect: usize) -> Option<board::Player> {
+    let rit = board_iterators::RowIterator::wrapping(board, connect);
     for row in rit {
-        for w in row.windows(4) {
-            if four_player_in_a_row(w, board::Player::Yellow) {
+        for w in row.windows(connect) {
+            if player_in_a_row(w, board::Player::Yellow) {
                 return Some(board::Player::Yellow);
             }
-            if four_player_in_a_row(w, board::Player::Red) {
+            if player_in_a_row(w, board::Player::Red) {
                 return Some(board::Player::Red);
             }
         }
@@ -22,14 +222,14 @@ fn find_winner_in_rows(board: &board::Board) -> Option<board::Player> {
     None
 }
 
-fn find_winner_in_cols(board: &board::Board) -> Option<board::Player> {
+fn find_winner_in_cols(board: &board::Board, connect: usize) -> Option<board::Player> {
     let rit = board_iterators::ColIterator::new(board);
     for row in rit {
-        for w in row.windows(4) {
-            if four_player_in_a_row(w, board::Player::Yellow) {
+        for w in row.windows(connect) {
+            if player_in_a_row(w, board::Player::Yellow) {
                 return Some(board::Player::Yellow);
             }
-            if four_player_in_a_row(w, board::Player::Red) {
+            if player_in_a_row(w, board::Player::Red) {
                 return Some(board::Player::Red);
             }
         }
@@ -37,15 +237,15 @@ fn find_winner_in_cols(board: &board::Board) -> Option<board::Player> {
     None
 }
 
-fn find_winner_in_diagonals(board: &board::Board) -> Option<board::Player> {
+fn find_winner_in_diagonals(board: &board::Board, connect: usize) -> Option<board::Player> {
     let it = board_iterators::MainDiagonalIterator::new(board)
         .chain(board_iterators::SecondaryDiagonalIterator::new(board));
     for row in it {
-        for w in row.windows(4) {
-            if four_player_in_a_row(w, board::Player::Yellow) {
+        for w in row.windows(connect) {
+            if player_in_a_row(w, board::Player::Yellow) {
                 return Some(board::Player::Yellow);
             }
-            if four_player_in_a_row(w, board::Player::Red) {
+            if player_in_a_row(w, board::Player::Red) {
                 return Some(board::Player::Red);
             }
         }
@@ -53,8 +253,8 @@ fn find_winner_in_diagonals(board: &board::Board) -> Option<board::Player> {
     None
 }
 
-fn four_player_in_a_row(w: &[&Option<board::Player>], p: board::Player) -> bool {
-    w == [&Some(p), &Some(p), &Some(p), &Some(p)]
+fn player_in_a_row(w: &[&Option<board::Player>], p: board::Player) -> bool {
+    w.iter().all(|&&c| c == Some(p))
 }
 
 #[cfg(test)]
@@ -77,7 +277,7 @@ mod test {
     #[test]
     fn there_is_no_winner() -> Result<(), board::PlayErr> {
         let board = setup_board(vec![1, 1, 1, 1, 1, 2, 2, 2, 2, 2])?;
-        let winner = get_winner(&board);
+        let winner = get_winner(&board, 4);
 
         assert_eq!(winner, None);
 
@@ -87,7 +287,7 @@ mod test {
     #[test]
     fn there_is_a_winner_in_a_colunm() -> Result<(), board::PlayErr> {
         let board = setup_board(vec![1, 2, 1, 2, 1, 2, 1])?;
-        let winner = find_winner_in_cols(&board);
+        let winner = find_winner_in_cols(&board, 4);
 
         assert_ne!(winner, None);
 
@@ -97,7 +297,7 @@ mod test {
     #[test]
     fn there_is_a_winner_in_a_row() -> Result<(), board::PlayErr> {
         let board = setup_board(vec![1, 1, 2, 2, 3, 3, 4])?;
-        let winner = find_winner_in_rows(&board);
+        let winner = find_winner_in_rows(&board, 4);
 
         assert_ne!(winner, None);
 
@@ -107,7 +307,7 @@ mod test {
     #[test]
     fn there_is_a_winner_in_the_main_diagonal() -> Result<(), board::PlayErr> {
         let board = setup_board(vec![1, 2, 2, 3, 3, 1, 3, 4, 4, 4, 4])?;
-        let winner = find_winner_in_diagonals(&board);
+        let winner = find_winner_in_diagonals(&board, 4);
 
         assert_ne!(winner, None);
 
@@ -117,10 +317,200 @@ mod test {
     #[test]
     fn there_is_a_winner_in_the_secundary_diagonal() -> Result<(), board::PlayErr> {
         let board = setup_board(vec![1, 1, 1, 1, 2, 2, 3, 2, 1, 3, 3, 4])?;
-        let winner = find_winner_in_diagonals(&board);
+        let winner = find_winner_in_diagonals(&board, 4);
 
         assert_ne!(winner, None);
 
         Ok(())
     }
+
+    #[test]
+    fn bitboard_and_scan_agree_on_the_winner() -> Result<(), board::PlayErr> {
+        let games = vec![
+            vec![1, 1, 1, 1, 1, 2, 2, 2, 2, 2],
+            vec![1, 2, 1, 2, 1, 2, 1],
+            vec![1, 1, 2, 2, 3, 3, 4],
+            vec![1, 2, 2, 3, 3, 1, 3, 4, 4, 4, 4],
+            vec![1, 1, 1, 1, 2, 2, 3, 2, 1, 3, 3, 4],
+        ];
+        for plays in games {
+            let board = setup_board(plays)?;
+            let scanned = find_winner_in_rows(&board, 4)
+                .or_else(|| find_winner_in_cols(&board, 4))
+                .or_else(|| find_winner_in_diagonals(&board, 4));
+            assert_eq!(get_winner(&board, 4).map(|i| i.player), scanned);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn winner_depends_on_connect_length() -> Result<(), board::PlayErr> {
+        let board = setup_board(vec![0, 0, 1, 1, 2])?;
+
+        assert_eq!(
+            get_winner(&board, 3).map(|i| i.player),
+            Some(board::Player::Yellow)
+        );
+        assert_eq!(get_winner(&board, 4), None);
+        assert_eq!(find_winner_in_rows(&board, 3), Some(board::Player::Yellow));
+        Ok(())
+    }
+
+    #[test]
+    fn winner_at_returns_the_winning_cells() -> Result<(), board::PlayErr> {
+        let board = setup_board(vec![1, 1, 2, 2, 3, 3, 4])?;
+
+        assert_eq!(
+            get_winner_at(&board, 4, 4, 4),
+            Some(WinInfo {
+                player: board::Player::Yellow,
+                lines: vec![WinLine {
+                    direction: Direction::Horizontal,
+                    cells: vec![(4, 1), (4, 2), (4, 3), (4, 4)],
+                }],
+            })
+        );
+        assert_eq!(get_winner_at(&board, 3, 3, 4), None);
+        assert_eq!(get_winner_at(&board, 0, 0, 4), None);
+        Ok(())
+    }
+
+    #[test]
+    fn winner_at_joins_every_line_through_the_piece() -> Result<(), board::PlayErr> {
+        let mut board = board::Board::new(5, 5);
+        for col in 0..3 {
+            for _ in 0..3 {
+                board.play(col, board::Player::Red)?;
+            }
+            board.play(col, board::Player::Yellow)?;
+        }
+        for _ in 0..4 {
+            board.play(3, board::Player::Yellow)?;
+        }
+        let info = get_winner_at(&board, 1, 3, 4).expect("yellow won");
+        let directions: Vec<Direction> = info.lines.iter().map(|l| l.direction).collect();
+        let cells = info.get_cells();
+
+        assert_eq!(info.player, board::Player::Yellow);
+        assert_eq!(directions, vec![Direction::Horizontal, Direction::Vertical]);
+        assert_eq!(cells.len(), 7);
+        assert!(cells.contains(&(1, 0)) && cells.contains(&(4, 3)));
+        Ok(())
+    }
+
+    #[test]
+    fn winner_at_agrees_with_full_scan() -> Result<(), board::PlayErr> {
+        let games = vec![
+            vec![1, 1, 1, 1, 1, 2, 2, 2, 2, 2],
+            vec![1, 2, 1, 2, 1, 2, 1],
+            vec![1, 1, 2, 2, 3, 3, 4],
+            vec![1, 2, 2, 3, 3, 1, 3, 4, 4, 4, 4],
+            vec![1, 1, 1, 1, 2, 2, 3, 2, 1, 3, 3, 4],
+        ];
+        for plays in games {
+            let board = setup_board(plays)?;
+            let col = board.get_last_move().expect("moves were played");
+            let row = board.get_rows() - board.get_height(col);
+            let at = get_winner_at(&board, row, col, 4).map(|i| i.player);
+            assert_eq!(at, get_winner(&board, 4).map(|i| i.player));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn get_winner_lists_every_winning_line() -> Result<(), board::PlayErr> {
+        // Yellow has five in a row on the bottom and four in column 0.
+        let mut board = board::Board::new(6, 7);
+        for &col in &[0, 1, 2, 3, 4, 0, 0, 0] {
+            board.play(col, board::Player::Yellow)?;
+        }
+        let info = get_winner(&board, 4).expect("yellow won");
+
+        assert_eq!(info.player, board::Player::Yellow);
+        assert_eq!(
+            info.lines,
+            vec![
+                WinLine {
+                    direction: Direction::Vertical,
+                    cells: vec![(2, 0), (3, 0), (4, 0), (5, 0)],
+                },
+                WinLine {
+                    direction: Direction::Horizontal,
+                    cells: vec![(5, 0), (5, 1), (5, 2), (5, 3), (5, 4)],
+                },
+            ]
+        );
+        assert_eq!(info.get_cells().len(), 8);
+        Ok(())
+    }
+
+    #[test]
+    fn pop_that_wins_for_both_players_wins_for_the_mover() -> Result<(), board::PlayErr> {
+        // Yellow pops column 0, dropping red onto the bottom row next to
+        // three red pieces and a yellow piece next to three yellow ones.
+        let mut board = board::Board::new(6, 7);
+        for &(col, player) in &[
+            (0, board::Player::Yellow),
+            (0, board::Player::Red),
+            (0, board::Player::Yellow),
+            (1, board::Player::Red),
+            (2, board::Player::Red),
+            (3, board::Player::Red),
+            (1, board::Player::Yellow),
+            (2, board::Player::Yellow),
+            (3, board::Player::Yellow),
+        ] {
+            board.play(col, player)?;
+        }
+        assert_eq!(
+            get_winner_in_column(&board, 0, 4, board::Player::Yellow),
+            None
+        );
+
+        board.pop(0, board::Player::Yellow)?;
+        let info = get_winner_in_column(&board, 0, 4, board::Player::Yellow).expect("both won");
+        assert_eq!(info.player, board::Player::Yellow);
+        assert_eq!(info.get_cells(), vec![(4, 0), (4, 1), (4, 2), (4, 3)]);
+        assert_eq!(
+            get_winner_in_column(&board, 0, 4, board::Player::Red).map(|i| i.player),
+            Some(board::Player::Red)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn lines_wrap_around_cylinders() -> Result<(), board::PlayErr> {
+        let mut flat = board::Board::new(6, 7);
+        let mut cylinder = board::Board::cylinder(6, 7);
+        for board in [&mut flat, &mut cylinder].iter_mut() {
+            for &col in &[5, 6, 0, 1] {
+                board.play(col, board::Player::Yellow)?;
+            }
+        }
+        assert_eq!(get_winner(&flat, 4), None);
+        assert_eq!(get_winner_at(&flat, 5, 0, 4), None);
+
+        let cells = vec![(5, 5), (5, 6), (5, 0), (5, 1)];
+        let info = get_winner(&cylinder, 4).expect("row wraps around");
+        assert_eq!(info.player, board::Player::Yellow);
+        assert_eq!(info.get_cells(), cells);
+        let info = get_winner_at(&cylinder, 5, 0, 4).expect("row wraps around");
+        assert_eq!(info.get_cells(), cells);
+        Ok(())
+    }
+
+    #[test]
+    fn full_cylinder_row_is_one_line() -> Result<(), board::PlayErr> {
+        let mut board = board::Board::cylinder(1, 4);
+        for col in 0..4 {
+            board.play(col, board::Player::Red)?;
+        }
+        let info = get_winner(&board, 3).expect("row is full");
+        assert_eq!(info.lines.len(), 1);
+        assert_eq!(info.get_cells(), vec![(0, 0), (0, 1), (0, 2), (0, 3)]);
+        let info = get_winner_at(&board, 0, 2, 3).expect("row is full");
+        assert_eq!(info.get_cells(), vec![(0, 3), (0, 0), (0, 1), (0, 2)]);
+        Ok(())
+    }
 }